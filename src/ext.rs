@@ -2,3 +2,159 @@ pub mod account {
     /// Public key account actor method.
     pub const PUBKEY_ADDRESS_METHOD: u64 = 2;
 }
+
+/// Typed client of the IPC gateway.
+///
+/// Methods that change the state of the gateway return the payload of the
+/// message so it can be queued in the outbox of the subnet, while queries
+/// are sent straight away and decode their return value.
+pub mod gateway {
+    #[cfg(feature = "gateway-extensions")]
+    use fil_actors_runtime::runtime::Runtime;
+    use fil_actors_runtime::ActorError;
+    #[cfg(feature = "gateway-extensions")]
+    use fvm_ipld_blockstore::Blockstore;
+    use fvm_ipld_encoding::RawBytes;
+    use fvm_shared::address::Address;
+    use fvm_shared::econ::TokenAmount;
+    #[cfg(feature = "gateway-extensions")]
+    use ipc_gateway::SubnetID;
+    use ipc_gateway::{Checkpoint, FundParams, Method};
+    use num_traits::Zero;
+
+    use crate::types::CrossActorPayload;
+
+    /// Gateway method returning the collateral it has recorded
+    /// for the subnet given as parameter.
+    #[cfg(feature = "gateway-extensions")]
+    pub const GET_COLLATERAL_METHOD: u64 = 13;
+
+    /// Gateway method paying the subnet the given amount out of the fees
+    /// collected for it, by calling `CollectFees` on the subnet actor.
+    #[cfg(feature = "gateway-extensions")]
+    pub const COLLECT_FEES_METHOD: u64 = 14;
+
+    /// Gateway method reverting a committed checkpoint of the subnet,
+    /// along with the checkpoints committed on top of it.
+    #[cfg(feature = "gateway-extensions")]
+    pub const REVERT_CHILD_CHECKPOINT_METHOD: u64 = 15;
+
+    /// Registers the subnet with its initial collateral.
+    pub fn register(gateway: Address, collateral: TokenAmount) -> CrossActorPayload {
+        CrossActorPayload::new(
+            gateway,
+            Method::Register as u64,
+            RawBytes::default(),
+            collateral,
+        )
+    }
+
+    /// Adds collateral to a registered subnet.
+    pub fn add_stake(gateway: Address, amount: TokenAmount) -> CrossActorPayload {
+        CrossActorPayload::new(
+            gateway,
+            Method::AddStake as u64,
+            RawBytes::default(),
+            amount,
+        )
+    }
+
+    /// Releases collateral of the subnet.
+    pub fn release_stake(
+        gateway: Address,
+        amount: TokenAmount,
+    ) -> Result<CrossActorPayload, ActorError> {
+        Ok(CrossActorPayload::new(
+            gateway,
+            Method::ReleaseStake as u64,
+            RawBytes::serialize(FundParams { value: amount })?,
+            TokenAmount::zero(),
+        ))
+    }
+
+    /// Unregisters the subnet.
+    pub fn kill(gateway: Address) -> CrossActorPayload {
+        CrossActorPayload::new(
+            gateway,
+            Method::Kill as u64,
+            RawBytes::default(),
+            TokenAmount::zero(),
+        )
+    }
+
+    /// Commits a checkpoint of the subnet.
+    pub fn commit_child_checkpoint(
+        gateway: Address,
+        ch: &Checkpoint,
+    ) -> Result<CrossActorPayload, ActorError> {
+        Ok(CrossActorPayload::new(
+            gateway,
+            Method::CommitChildCheckpoint as u64,
+            RawBytes::serialize(ch)?,
+            TokenAmount::zero(),
+        ))
+    }
+
+    /// Reverts a checkpoint of the subnet that was proven fraudulent.
+    #[cfg(feature = "gateway-extensions")]
+    pub fn revert_child_checkpoint(
+        gateway: Address,
+        ch: &Checkpoint,
+    ) -> Result<CrossActorPayload, ActorError> {
+        Ok(CrossActorPayload::new(
+            gateway,
+            REVERT_CHILD_CHECKPOINT_METHOD,
+            RawBytes::serialize(ch)?,
+            TokenAmount::zero(),
+        ))
+    }
+
+    /// Requests the fees charged by the subnet for the commitment of a checkpoint.
+    #[cfg(feature = "gateway-extensions")]
+    pub fn collect_fees(
+        gateway: Address,
+        amount: TokenAmount,
+    ) -> Result<CrossActorPayload, ActorError> {
+        Ok(CrossActorPayload::new(
+            gateway,
+            COLLECT_FEES_METHOD,
+            RawBytes::serialize(FundParams { value: amount })?,
+            TokenAmount::zero(),
+        ))
+    }
+
+    /// Returns the collateral change a delivered message applies in the gateway,
+    /// as the amount added and the amount released.
+    pub fn collateral_change(
+        p: &CrossActorPayload,
+    ) -> Result<(TokenAmount, TokenAmount), ActorError> {
+        if p.method == Method::Register as u64 || p.method == Method::AddStake as u64 {
+            Ok((p.value.clone(), TokenAmount::zero()))
+        } else if p.method == Method::ReleaseStake as u64 {
+            let params: FundParams = p.params.deserialize()?;
+            Ok((TokenAmount::zero(), params.value))
+        } else {
+            Ok((TokenAmount::zero(), TokenAmount::zero()))
+        }
+    }
+
+    /// Queries the collateral the gateway has recorded for a subnet.
+    #[cfg(feature = "gateway-extensions")]
+    pub fn get_collateral<BS, RT>(
+        rt: &mut RT,
+        gateway: Address,
+        subnet: &SubnetID,
+    ) -> Result<TokenAmount, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        let ret = rt.send(
+            gateway,
+            GET_COLLATERAL_METHOD,
+            RawBytes::serialize(subnet)?,
+            TokenAmount::zero(),
+        )?;
+        Ok(ret.deserialize()?)
+    }
+}
@@ -4,15 +4,23 @@ pub mod ext;
 pub mod state;
 pub mod types;
 
+use fil_actors_runtime::runtime::builtins::Type;
+use fil_actors_runtime::runtime::fvm::resolve_secp_bls;
 use fil_actors_runtime::runtime::{ActorCode, Runtime};
-use fil_actors_runtime::{actor_error, cbor, ActorDowncast, ActorError, INIT_ACTOR_ADDR};
+use fil_actors_runtime::{
+    actor_error, cbor, ActorDowncast, ActorError, BURNT_FUNDS_ACTOR_ADDR, INIT_ACTOR_ADDR,
+};
 use fvm_ipld_blockstore::Blockstore;
 use fvm_ipld_encoding::RawBytes;
 
+use fvm_shared::address::{Address, Protocol};
+use fvm_shared::clock::ChainEpoch;
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::error::ExitCode;
-use fvm_shared::{MethodNum, METHOD_CONSTRUCTOR};
-use ipc_gateway::{Checkpoint, FundParams, MIN_COLLATERAL_AMOUNT};
+use fvm_shared::{MethodNum, METHOD_CONSTRUCTOR, METHOD_SEND};
+#[cfg(feature = "gateway-extensions")]
+use ipc_gateway::FundParams;
+use ipc_gateway::{Checkpoint, SubnetID};
 use num_derive::FromPrimitive;
 use num_traits::{FromPrimitive, Zero};
 
@@ -30,6 +38,29 @@ pub enum Method {
     Leave = 3,
     Kill = 4,
     SubmitCheckpoint = 5,
+    FinalizeKill = 6,
+    GenesisCid = 7,
+    SubmitFraudProof = 8,
+    LivenessStats = 9,
+    FundRewards = 10,
+    ClaimRewards = 11,
+    Delegate = 12,
+    Undelegate = 13,
+    WithdrawUnbonded = 14,
+    SetCommission = 15,
+    RotateSigningKey = 16,
+    SigningKeyHistory = 17,
+    UpdateAllowList = 18,
+    AllowList = 19,
+    ProposeRemoval = 20,
+    FlushOutbox = 21,
+    CollateralStatus = 22,
+    SyncStake = 23,
+    #[cfg(feature = "gateway-extensions")]
+    NotifyFund = 24,
+    CollectFees = 25,
+    Donate = 26,
+    ProposeSpend = 27,
 }
 
 /// SubnetActor trait. Custom subnet actors need to implement this trait
@@ -85,12 +116,48 @@ impl SubnetActor for Actor {
     {
         rt.validate_immediate_caller_is(std::iter::once(&*INIT_ACTOR_ADDR))?;
 
-        let st = State::new(rt.store(), params).map_err(|e| {
+        // the stake of initial validators is funded by the constructor's value.
+        let seeded = params
+            .initial_validators
+            .iter()
+            .fold(TokenAmount::zero(), |acc, v| acc + &v.stake);
+        if seeded != rt.message().value_received() {
+            return Err(actor_error!(
+                illegal_argument,
+                "value sent doesn't match the stake of the initial validators"
+            ));
+        }
+
+        let mut st = State::new(rt.store(), params).map_err(|e| {
             e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "Failed to create actor state")
         })?;
+        // initial validators go through the same checks as joining ones,
+        // and sign checkpoints with the key of their account.
+        for i in 0..st.validator_set.len() {
+            let addr = st.validator_set[i].addr;
+            if !check_validator_caller(rt, &st, &addr)? {
+                return Err(actor_error!(
+                    illegal_argument,
+                    "initial validator {} needs to be an account",
+                    addr
+                ));
+            }
+            st.validator_set[i].key_addr = resolve_key(rt, &addr)?;
+        }
+        update_status(&mut st, rt.store(), rt.curr_epoch())?;
+
+        // register straight away if the initial validators activated the subnet
+        if st.status == Status::Active {
+            st.outbox.push(ext::gateway::register(
+                st.ipc_gateway_addr,
+                st.total_stake.clone(),
+            ));
+        }
 
         rt.create(&st)?;
 
+        deliver_outbox(rt)?;
+
         Ok(())
     }
 
@@ -105,7 +172,6 @@ impl SubnetActor for Actor {
         rt.validate_immediate_caller_accept_any()?;
 
         let caller = rt.message().caller();
-        // TODO: shall we check caller interface instead here?
 
         let amount = rt.message().value_received();
         if amount == TokenAmount::zero() {
@@ -115,42 +181,118 @@ impl SubnetActor for Actor {
             ));
         }
 
-        let mut msg = None;
+        // new validators sign checkpoints with their signing key if
+        // they registered one, or with the key of their account.
+        let state: State = rt.state()?;
+        if !state.is_allowed(&caller) {
+            return Err(actor_error!(
+                forbidden,
+                "{} is not in the allow-list of the subnet",
+                caller
+            ));
+        }
+        let is_account = check_validator_caller(rt, &state, &caller)?;
+        let is_validator = state.is_validator(&caller);
+        let key_addr = match params.signing_key {
+            Some(_) if is_validator => {
+                return Err(actor_error!(
+                    illegal_argument,
+                    "the signing key of validators can only be changed with RotateSigningKey"
+                ));
+            }
+            Some(key) => {
+                check_signing_key(rt, &caller, &key)?;
+                key.key
+            }
+            None if is_validator => caller,
+            None if is_account => resolve_key(rt, &caller)?,
+            None => {
+                return Err(actor_error!(
+                    illegal_argument,
+                    "validators other than accounts need to register a signing key"
+                ));
+            }
+        };
+
+        let epoch = rt.curr_epoch();
         rt.transaction(|st: &mut State, rt| {
-            // increase collateral
-            st.add_stake(rt.store(), &caller, &params.validator_net_addr, &amount)
+            match st.status {
+                Status::Terminating | Status::Killed => {
+                    return Err(actor_error!(
+                        illegal_state,
+                        "cannot join a subnet that is terminating or killed"
+                    ));
+                }
+                Status::Inactive if st.inactive_grace_expired(epoch) => {
+                    return Err(actor_error!(
+                        illegal_state,
+                        "the inactivity grace period of the subnet has expired"
+                    ));
+                }
+                _ => {}
+            }
+
+            // ejected validators need to wait before joining again
+            let rejoin_epoch = st.get_rejoin_epoch(rt.store(), &caller).map_err(|e| {
+                e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "failed to load rejoin epoch")
+            })?;
+            if let Some(rejoin_epoch) = rejoin_epoch.filter(|e| epoch < *e) {
+                return Err(actor_error!(
+                    forbidden,
+                    "{} was ejected and can't join the subnet until epoch {}",
+                    caller,
+                    rejoin_epoch
+                ));
+            }
+
+            // stakers need to become validators
+            let stake = st
+                .get_stake(rt.store(), &caller)
                 .map_err(|e| {
-                    e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "failed to load subnet")
-                })?;
+                    e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "failed to load stake")
+                })?
+                .unwrap_or_else(TokenAmount::zero);
+            if stake + &amount < st.min_validator_stake {
+                return Err(actor_error!(
+                    illegal_argument,
+                    "the stake of the caller would be below the minimum validator stake"
+                ));
+            }
+
+            // signatures need to be attributable to a single validator
+            st.check_key_unused(&key_addr, &caller, epoch)?;
+
+            // increase collateral
+            st.add_stake(
+                rt.store(),
+                &caller,
+                &key_addr,
+                &params.validator_net_addr,
+                &amount,
+            )
+            .map_err(|e| {
+                e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "failed to load subnet")
+            })?;
 
-            let total_stake = st.total_stake.clone();
+            let prev_status = st.status;
+            update_status(st, rt.store(), epoch)?;
 
-            if st.status == Status::Instantiated {
-                if total_stake >= TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT) {
-                    msg = Some(CrossActorPayload::new(
+            if prev_status == Status::Instantiated {
+                if st.status == Status::Active {
+                    st.outbox.push(ext::gateway::register(
                         st.ipc_gateway_addr,
-                        ipc_gateway::Method::Register as u64,
-                        RawBytes::default(),
-                        total_stake,
+                        st.total_stake.clone(),
                     ));
                 }
             } else {
-                msg = Some(CrossActorPayload::new(
-                    st.ipc_gateway_addr,
-                    ipc_gateway::Method::AddStake as u64,
-                    RawBytes::default(),
-                    amount,
-                ));
+                st.outbox
+                    .push(ext::gateway::add_stake(st.ipc_gateway_addr, amount));
             }
 
-            st.mutate_state();
-
             Ok(true)
         })?;
 
-        if let Some(p) = msg {
-            rt.send(p.to, p.method, p.params, p.value)?;
-        }
+        deliver_outbox(rt)?;
 
         Ok(None)
     }
@@ -165,14 +307,15 @@ impl SubnetActor for Actor {
 
         let caller = rt.message().caller();
 
-        // TODO: shall we check caller interface instead here?
-        // let code_cid = get_actor_code_cid(&caller).unwrap_or(Cid::default());
-        // if sdk::actor::get_builtin_actor_type(&code_cid) != Some(Type::Account) {
-        //     abort!(USR_FORBIDDEN, "caller not account actor type");
-        // }
+        let state: State = rt.state()?;
+        check_validator_caller(rt, &state, &caller)?;
 
-        let mut msg = None;
+        let epoch = rt.curr_epoch();
         rt.transaction(|st: &mut State, rt| {
+            if st.status == Status::Killed {
+                return Err(actor_error!(illegal_state, "the subnet has been killed"));
+            }
+
             let stake = st.get_stake(rt.store(), &caller).map_err(|e| {
                 e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "failed to load stake")
             })?;
@@ -182,34 +325,58 @@ impl SubnetActor for Actor {
             }
 
             let stake = stake.unwrap();
-            if st.status != Status::Terminating {
-                msg = Some(CrossActorPayload::new(
-                    st.ipc_gateway_addr,
-                    ipc_gateway::Method::ReleaseStake as u64,
-                    RawBytes::serialize(FundParams {
-                        value: stake.clone(),
-                    })?,
-                    TokenAmount::zero(),
-                ));
-            }
 
             // remove stake from balance table
             st.rm_stake(&rt.store(), &caller, &stake).map_err(|e| {
                 e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot remove stake")
             })?;
 
-            st.mutate_state();
+            // the stake delegated to the validator goes through unbonding
+            let undelegated = st.undelegate_all(rt.store(), &caller, epoch).map_err(|e| {
+                e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot undelegate stake")
+            })?;
+
+            // the stake of terminating subnets is returned by the gateway
+            // with the kill, and it's paid out without penalty.
+            if st.status == Status::Terminating {
+                st.outbox.push(CrossActorPayload::new(
+                    caller,
+                    METHOD_SEND,
+                    RawBytes::default(),
+                    stake,
+                ));
+            } else {
+                let (released, burnt) =
+                    st.charge_leaving_penalty(rt.store(), &stake).map_err(|e| {
+                        e.downcast_default(
+                            ExitCode::USR_ILLEGAL_STATE,
+                            "cannot charge leaving penalty",
+                        )
+                    })?;
+
+                st.outbox.push(ext::gateway::release_stake(
+                    st.ipc_gateway_addr,
+                    released + &burnt + undelegated,
+                )?);
+                if burnt > TokenAmount::zero() {
+                    st.outbox.push(burn(burnt));
+                }
+            }
+
+            update_status(st, rt.store(), epoch)?;
 
             Ok(true)
         })?;
 
-        if let Some(p) = msg {
-            rt.send(p.to, p.method, p.params, p.value)?;
-        }
+        deliver_outbox(rt)?;
 
         Ok(None)
     }
 
+    /// Starts the termination of the subnet.
+    ///
+    /// Subnets can be killed once all validators have left, or by anyone
+    /// once they have been inactive for longer than `INACTIVE_GRACE_PERIOD`.
     fn kill<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
     where
         BS: Blockstore,
@@ -217,15 +384,19 @@ impl SubnetActor for Actor {
     {
         rt.validate_immediate_caller_accept_any()?;
 
-        // prevent a subnet from being killed until all its locked balance has been withdrawn
-        if rt.current_balance() != TokenAmount::zero() {
+        // prevent a subnet from being killed until all its locked balance has been withdrawn.
+        // Unclaimed rewards can still be claimed after the subnet is killed, while the
+        // undistributed reward pool and the treasury are returned to the beneficiary.
+        let state: State = rt.state()?;
+        let locked = rt.current_balance() - state.reward_funds() - &state.treasury;
+        if locked != TokenAmount::zero() {
             return Err(actor_error!(
                 illegal_state,
-                format!("the subnet has non-zero balance: {:}", rt.current_balance())
+                format!("the subnet has non-zero balance: {:}", locked)
             ));
         }
 
-        let mut msg = None;
+        let epoch = rt.curr_epoch();
         rt.transaction(|st: &mut State, _| {
             if st.status == Status::Terminating || st.status == Status::Killed {
                 return Err(actor_error!(
@@ -234,32 +405,26 @@ impl SubnetActor for Actor {
                 ));
             }
 
-            if !st.validator_set.is_empty() || st.total_stake != TokenAmount::zero() {
+            // subnets that exceeded their inactivity grace period can be
+            // killed by anyone even if some stake is left behind.
+            if !st.inactive_grace_expired(epoch)
+                && (!st.validator_set.is_empty()
+                    || st.total_stake != TokenAmount::zero()
+                    || st.total_delegated != TokenAmount::zero())
+            {
                 return Err(actor_error!(
                     illegal_state,
-                    "this subnet can only be killed when all validators have left"
+                    "this subnet can only be killed when all validators and delegators have left"
                 ));
             }
 
-            // move to terminating state
-            st.status = Status::Terminating;
-
-            st.mutate_state();
-
-            msg = Some(CrossActorPayload::new(
-                st.ipc_gateway_addr,
-                ipc_gateway::Method::Kill as u64,
-                RawBytes::default(),
-                TokenAmount::zero(),
-            ));
+            terminate(st);
 
             Ok(true)
         })?;
 
         // unregister subnet
-        if let Some(p) = msg {
-            rt.send(p.to, p.method, p.params, p.value)?;
-        }
+        deliver_outbox(rt)?;
 
         Ok(None)
     }
@@ -281,16 +446,13 @@ impl SubnetActor for Actor {
 
         let state: State = rt.state()?;
         let caller = rt.message().caller();
-
-        if !state.is_validator(&caller) {
-            return Err(actor_error!(illegal_state, "not validator"));
-        }
+        let validator = checkpoint_submitter(rt, &state, &caller)?;
 
         state
-            .verify_checkpoint(rt, &ch)
-            .map_err(|_| actor_error!(illegal_state, "checkpoint failed"))?;
+            .verify_checkpoint(rt, &ch, &validator)
+            .map_err(|e| e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "checkpoint failed"))?;
 
-        let mut msg = None;
+        let epoch = rt.curr_epoch();
 
         rt.transaction(|st: &mut State, rt| {
             let ch_cid = ch.cid();
@@ -306,7 +468,7 @@ impl SubnetActor for Actor {
                 },
             };
 
-            if votes.validators.iter().any(|x| x == &caller) {
+            if votes.validators.iter().any(|x| x == &validator) {
                 return Err(actor_error!(
                     illegal_state,
                     "miner has already voted the checkpoint"
@@ -314,26 +476,58 @@ impl SubnetActor for Actor {
             }
 
             // add miner vote
-            votes.validators.push(caller);
+            votes.validators.push(validator);
 
             // if has majority
             if st.has_majority_vote(rt.store(), &votes)? {
+                // reward the validators that signed the checkpoint
+                let rewards = st
+                    .distribute_rewards(rt.store(), &votes.validators)
+                    .map_err(|e| {
+                        e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot distribute rewards")
+                    })?;
+
                 // commit checkpoint
-                st.flush_checkpoint(rt.store(), &ch)
+                st.flush_checkpoint(rt.store(), &ch, &votes.validators, rewards, epoch)
                     .map_err(|_| actor_error!(illegal_state, "cannot flush checkpoint"))?;
 
                 // prepare the message
-                msg = Some(CrossActorPayload::new(
+                st.outbox.push(ext::gateway::commit_child_checkpoint(
                     st.ipc_gateway_addr,
-                    ipc_gateway::Method::CommitChildCheckpoint as u64,
-                    RawBytes::serialize(ch)?,
-                    TokenAmount::zero(),
-                ));
+                    &ch,
+                )?);
+
+                // charge the fees of the subnet for the commitment
+                #[cfg(feature = "gateway-extensions")]
+                let fee = st.fee_config.checkpoint_fee(&ch);
+                #[cfg(feature = "gateway-extensions")]
+                if fee > TokenAmount::zero() {
+                    st.outbox
+                        .push(ext::gateway::collect_fees(st.ipc_gateway_addr, fee)?);
+                }
 
                 // remove votes used for commitment
                 if found {
                     st.remove_votes(rt.store(), &ch_cid)?;
                 }
+
+                // penalize validators not participating in checkpointing
+                let (burnt, ejected) = st
+                    .apply_participation_penalties(rt.store(), epoch)
+                    .map_err(|e| {
+                        e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot apply penalties")
+                    })?;
+                let released = burnt.clone() + ejected;
+                if released > TokenAmount::zero() {
+                    st.outbox
+                        .push(ext::gateway::release_stake(st.ipc_gateway_addr, released)?);
+                }
+                if burnt > TokenAmount::zero() {
+                    st.outbox.push(burn(burnt));
+                }
+
+                // subnets inactive due to missed windows recover on commitment
+                update_status(st, rt.store(), epoch)?;
             } else {
                 // if no majority store vote and return
                 st.set_votes(rt.store(), &ch_cid, votes)?;
@@ -343,43 +537,1149 @@ impl SubnetActor for Actor {
         })?;
 
         // propagate to sca
-        if let Some(p) = msg {
-            rt.send(p.to, p.method, p.params, p.value)?;
-        }
+        deliver_outbox(rt)?;
 
         Ok(None)
     }
 }
 
-impl ActorCode for Actor {
-    fn invoke_method<BS, RT>(
+impl Actor {
+    /// Completes the termination of the subnet.
+    ///
+    /// It moves a terminating subnet to `Killed` once it has been unregistered
+    /// from the gateway and all the stake has been recovered by its owners.
+    /// After this, the subnet refuses every state-changing method except the
+    /// ones that pay out funds still held by the subnet: `ClaimRewards`,
+    /// `WithdrawUnbonded` and `FlushOutbox`.
+    pub fn finalize_kill<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        rt.transaction(|st: &mut State, _| {
+            if st.status != Status::Terminating {
+                return Err(actor_error!(
+                    illegal_state,
+                    "only terminating subnets can be finalized"
+                ));
+            }
+
+            if !st.can_finalize_kill() {
+                return Err(actor_error!(
+                    illegal_state,
+                    "the subnet is not unregistered or still holds stake"
+                ));
+            }
+
+            st.status = Status::Killed;
+
+            Ok(true)
+        })?;
+
+        Ok(None)
+    }
+
+    /// Returns the CID of the genesis committed for the subnet.
+    pub fn genesis_cid<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let st: State = rt.state()?;
+        Ok(Some(RawBytes::serialize(st.genesis_cid)?))
+    }
+
+    /// Returns the liveness statistics of the subnet.
+    pub fn liveness_stats<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let st: State = rt.state()?;
+        Ok(Some(RawBytes::serialize(
+            st.liveness_stats(rt.curr_epoch()),
+        )?))
+    }
+
+    /// Adds the value received to the pool used to reward checkpoint signers.
+    ///
+    /// Anyone can fund the pool, e.g. the owner of the subnet or the gateway
+    /// forwarding the fees collected by the subnet.
+    pub fn fund_rewards<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let amount = rt.message().value_received();
+        if amount == TokenAmount::zero() {
+            return Err(actor_error!(
+                illegal_argument,
+                "no funds sent to the reward pool"
+            ));
+        }
+
+        rt.transaction(|st: &mut State, _| {
+            if st.status == Status::Terminating || st.status == Status::Killed {
+                return Err(actor_error!(
+                    illegal_state,
+                    "cannot fund the rewards of a subnet that is terminating or killed"
+                ));
+            }
+
+            st.reward_pool += amount;
+
+            Ok(true)
+        })?;
+
+        Ok(None)
+    }
+
+    /// Sends the caller the checkpoint rewards it has accumulated.
+    pub fn claim_rewards<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let caller = rt.message().caller();
+        let amount = rt.transaction(|st: &mut State, rt| {
+            st.claim_rewards(rt.store(), &caller).map_err(|e| {
+                e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot claim rewards")
+            })
+        })?;
+
+        if amount == TokenAmount::zero() {
+            return Err(actor_error!(
+                illegal_state,
+                "caller has no rewards to claim"
+            ));
+        }
+
+        rt.send(caller, METHOD_SEND, RawBytes::default(), amount)?;
+
+        Ok(None)
+    }
+
+    /// Delegates the value received to a validator of the subnet.
+    ///
+    /// Delegated stake counts toward the voting power of the validator, and
+    /// it shares the rewards and slashing of the validator proportionally.
+    pub fn delegate<BS, RT>(
         rt: &mut RT,
-        method: MethodNum,
-        params: &RawBytes,
-    ) -> Result<RawBytes, ActorError>
+        params: DelegateParams,
+    ) -> Result<Option<RawBytes>, ActorError>
     where
         BS: Blockstore,
         RT: Runtime<BS>,
     {
-        match FromPrimitive::from_u64(method) {
-            Some(Method::Constructor) => {
-                Self::constructor(rt, cbor::deserialize_params(params)?)?;
-                Ok(RawBytes::default())
+        rt.validate_immediate_caller_accept_any()?;
+
+        let caller = rt.message().caller();
+        let amount = rt.message().value_received();
+        if amount == TokenAmount::zero() || amount != params.amount {
+            return Err(actor_error!(
+                illegal_argument,
+                "value sent doesn't match the amount delegated"
+            ));
+        }
+
+        rt.transaction(|st: &mut State, rt| {
+            if st.status != Status::Active {
+                return Err(actor_error!(
+                    illegal_state,
+                    "stake can only be delegated in active subnets"
+                ));
             }
-            Some(Method::Join) => {
-                let res = Self::join(rt, cbor::deserialize_params(params)?)?;
-                Ok(RawBytes::serialize(res)?)
+            if !st.is_validator(&params.validator) {
+                return Err(actor_error!(
+                    illegal_argument,
+                    "{} is not a validator of the subnet",
+                    params.validator
+                ));
             }
-            Some(Method::Leave) => {
-                let res = Self::leave(rt)?;
-                Ok(RawBytes::serialize(res)?)
+
+            st.add_delegation(rt.store(), &caller, &params.validator, &amount)
+                .map_err(|e| {
+                    e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot delegate stake")
+                })?;
+
+            st.outbox
+                .push(ext::gateway::add_stake(st.ipc_gateway_addr, amount));
+
+            Ok(true)
+        })?;
+
+        deliver_outbox(rt)?;
+
+        Ok(None)
+    }
+
+    /// Undelegates stake from a validator.
+    ///
+    /// The stake is released from the gateway and can be withdrawn with
+    /// `WithdrawUnbonded` once the unbonding period is over.
+    pub fn undelegate<BS, RT>(
+        rt: &mut RT,
+        params: UndelegateParams,
+    ) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let caller = rt.message().caller();
+        if params.amount <= TokenAmount::zero() {
+            return Err(actor_error!(
+                illegal_argument,
+                "amount to undelegate must be positive"
+            ));
+        }
+
+        let epoch = rt.curr_epoch();
+        rt.transaction(|st: &mut State, rt| {
+            if st.status == Status::Killed {
+                return Err(actor_error!(illegal_state, "the subnet has been killed"));
             }
-            Some(Method::Kill) => {
-                let res = Self::kill(rt)?;
-                Ok(RawBytes::serialize(res)?)
+
+            st.rm_delegation(rt.store(), &caller, &params.validator, &params.amount)
+                .map_err(|e| {
+                    e.downcast_default(ExitCode::USR_ILLEGAL_ARGUMENT, "cannot undelegate stake")
+                })?;
+            st.add_unbonding(rt.store(), &caller, &params.amount, epoch)
+                .map_err(|e| {
+                    e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot unbond stake")
+                })?;
+
+            // the stake of terminating subnets has already been released
+            if st.status != Status::Terminating {
+                st.outbox.push(ext::gateway::release_stake(
+                    st.ipc_gateway_addr,
+                    params.amount.clone(),
+                )?);
             }
-            Some(Method::SubmitCheckpoint) => {
-                let res = Self::submit_checkpoint(rt, cbor::deserialize_params(params)?)?;
+
+            Ok(true)
+        })?;
+
+        deliver_outbox(rt)?;
+
+        Ok(None)
+    }
+
+    /// Sends the caller the undelegated stake whose unbonding period is over.
+    pub fn withdraw_unbonded<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let caller = rt.message().caller();
+        let epoch = rt.curr_epoch();
+        let amount = rt.transaction(|st: &mut State, rt| {
+            st.withdraw_unbonded(rt.store(), &caller, epoch)
+                .map_err(|e| {
+                    e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot withdraw stake")
+                })
+        })?;
+
+        if amount == TokenAmount::zero() {
+            return Err(actor_error!(
+                illegal_state,
+                "caller has no unbonded stake to withdraw"
+            ));
+        }
+
+        rt.send(caller, METHOD_SEND, RawBytes::default(), amount)?;
+
+        Ok(None)
+    }
+
+    /// Sets the commission rate the caller keeps from its checkpoint rewards.
+    pub fn set_commission<BS, RT>(
+        rt: &mut RT,
+        params: SetCommissionParams,
+    ) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let caller = rt.message().caller();
+        let epoch = rt.curr_epoch();
+        rt.transaction(|st: &mut State, _| {
+            st.set_commission(&caller, params.commission, epoch)?;
+            Ok(true)
+        })?;
+
+        Ok(None)
+    }
+
+    /// Rotates the key the caller signs checkpoints with.
+    ///
+    /// The new key takes effect after `SIGNING_KEY_ROTATION_DELAY` epochs, and
+    /// the previous one is still accepted for `key_rotation_windows` checkpoint
+    /// windows after that so checkpoints in flight can be signed with it.
+    pub fn rotate_signing_key<BS, RT>(
+        rt: &mut RT,
+        params: SigningKeyParams,
+    ) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let caller = rt.message().caller();
+        check_signing_key(rt, &caller, &params)?;
+
+        let epoch = rt.curr_epoch();
+        rt.transaction(|st: &mut State, rt| {
+            st.rotate_signing_key(rt.store(), &caller, &params.key, epoch)?;
+            Ok(true)
+        })?;
+
+        Ok(None)
+    }
+
+    /// Returns the history of rotations of the signing key of a validator.
+    pub fn signing_key_history<BS, RT>(
+        rt: &mut RT,
+        addr: Address,
+    ) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let st: State = rt.state()?;
+        let history = st.get_key_history(rt.store(), &addr).map_err(|e| {
+            e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "failed to load key history")
+        })?;
+        Ok(Some(RawBytes::serialize(history)?))
+    }
+
+    /// Adds or removes an address from the allow-list of a permissioned subnet.
+    ///
+    /// Updates from the owner of the subnet are applied straight away, while
+    /// updates from validators are applied once a majority of the voting power
+    /// has voted for them.
+    pub fn update_allow_list<BS, RT>(
+        rt: &mut RT,
+        params: AllowListParams,
+    ) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let caller = rt.message().caller();
+        rt.transaction(|st: &mut State, rt| {
+            if !st.permissioned {
+                return Err(actor_error!(
+                    illegal_state,
+                    "the subnet is not permissioned"
+                ));
+            }
+            if matches!(st.status, Status::Terminating | Status::Killed) {
+                return Err(actor_error!(
+                    illegal_state,
+                    "cannot update the allow-list of a subnet that is terminating or killed"
+                ));
+            }
+            if st.owner == Some(caller) {
+                st.update_allow_list(&params);
+            } else if st.is_validator(&caller) {
+                st.vote_allow_list(rt.store(), &caller, &params)?;
+            } else {
+                return Err(actor_error!(
+                    forbidden,
+                    "only the owner or validators can update the allow-list"
+                ));
+            }
+            Ok(true)
+        })?;
+
+        Ok(None)
+    }
+
+    /// Returns the allow-list of a permissioned subnet.
+    pub fn allow_list<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let st: State = rt.state()?;
+        if !st.permissioned {
+            return Err(actor_error!(
+                illegal_state,
+                "the subnet is not permissioned"
+            ));
+        }
+        Ok(Some(RawBytes::serialize(st.allow_list)?))
+    }
+
+    /// Votes to remove a validator from the validator set.
+    ///
+    /// Once validators with a majority of the voting power have voted for the
+    /// removal, the validator is ejected and its stake moved to unbonding, from
+    /// where it can be withdrawn with `WithdrawUnbonded`.
+    pub fn propose_removal<BS, RT>(
+        rt: &mut RT,
+        target: Address,
+    ) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let caller = rt.message().caller();
+        let epoch = rt.curr_epoch();
+        rt.transaction(|st: &mut State, rt| {
+            if matches!(st.status, Status::Terminating | Status::Killed) {
+                return Err(actor_error!(
+                    illegal_state,
+                    "cannot remove validators from a subnet that is terminating or killed"
+                ));
+            }
+            if !st.is_validator(&caller) {
+                return Err(actor_error!(
+                    forbidden,
+                    "only validators can propose removals"
+                ));
+            }
+            if !st.is_validator(&target) {
+                return Err(actor_error!(
+                    illegal_argument,
+                    "{} is not a validator",
+                    target
+                ));
+            }
+
+            if let Some(stake) = st.vote_removal(rt.store(), &caller, &target, epoch)? {
+                st.outbox
+                    .push(ext::gateway::release_stake(st.ipc_gateway_addr, stake)?);
+                update_status(st, rt.store(), epoch)?;
+            }
+
+            Ok(true)
+        })?;
+
+        deliver_outbox(rt)?;
+
+        Ok(None)
+    }
+
+    /// Retries the delivery of the messages pending in the outbox and
+    /// returns the collateral status of the subnet afterwards.
+    pub fn flush_outbox<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        deliver_outbox(rt)?;
+
+        let st: State = rt.state()?;
+        Ok(Some(RawBytes::serialize(st.collateral_status())?))
+    }
+
+    /// Reconciles the collateral recorded by the gateway with the stake of the subnet.
+    ///
+    /// It sends the `AddStake` or `ReleaseStake` needed to match the local stake and
+    /// delegations. Added collateral is only funded with the stake that never reached
+    /// the gateway. The collateral of the subnet is queried from the gateway when built
+    /// with the `gateway-extensions` feature, and it's otherwise taken from the messages
+    /// delivered to the gateway.
+    pub fn sync_stake<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let st: State = rt.state()?;
+        if !matches!(st.status, Status::Active | Status::Inactive) {
+            return Err(actor_error!(
+                illegal_state,
+                "only the stake of registered subnets can be synced"
+            ));
+        }
+        // pending messages would be corrected twice
+        if !st.outbox.is_empty() {
+            return Err(actor_error!(
+                illegal_state,
+                "the outbox needs to be flushed before syncing the stake"
+            ));
+        }
+
+        #[cfg(feature = "gateway-extensions")]
+        let collateral = {
+            let subnet = SubnetID::new(&st.parent_id, rt.message().receiver());
+            ext::gateway::get_collateral(rt, st.ipc_gateway_addr, &subnet)?
+        };
+        #[cfg(not(feature = "gateway-extensions"))]
+        let collateral = st.gateway_collateral.clone();
+
+        rt.transaction(|st: &mut State, _| {
+            st.gateway_collateral = collateral.clone();
+
+            let local = st.total_stake.clone() + &st.total_delegated;
+            if local > collateral {
+                let missing = local - collateral;
+                let amount = if missing > st.unforwarded_stake {
+                    st.unforwarded_stake.clone()
+                } else {
+                    missing
+                };
+                if amount > TokenAmount::zero() {
+                    st.unforwarded_stake -= &amount;
+                    st.outbox
+                        .push(ext::gateway::add_stake(st.ipc_gateway_addr, amount));
+                }
+            } else if local < collateral {
+                st.outbox.push(ext::gateway::release_stake(
+                    st.ipc_gateway_addr,
+                    collateral - local,
+                )?);
+            }
+
+            Ok(true)
+        })?;
+
+        deliver_outbox(rt)?;
+
+        let st: State = rt.state()?;
+        Ok(Some(RawBytes::serialize(st.collateral_status())?))
+    }
+
+    /// Records value funded top-down into the subnet.
+    ///
+    /// It's called by the gateway for every fund to the subnet, and it increases
+    /// the circulating supply that bounds the value checkpoints can release. The
+    /// upstream gateway doesn't notify subnets of funds yet, so it's only available
+    /// with the `gateway-extensions` feature.
+    #[cfg(feature = "gateway-extensions")]
+    pub fn notify_fund<BS, RT>(
+        rt: &mut RT,
+        params: FundParams,
+    ) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        let st: State = rt.state()?;
+        rt.validate_immediate_caller_is(std::iter::once(&st.ipc_gateway_addr))?;
+
+        if params.value <= TokenAmount::zero() {
+            return Err(actor_error!(
+                illegal_argument,
+                "funded value must be positive"
+            ));
+        }
+
+        rt.transaction(|st: &mut State, _| {
+            if st.status == Status::Killed {
+                return Err(actor_error!(illegal_state, "the subnet has been killed"));
+            }
+
+            st.circ_supply += params.value;
+
+            Ok(true)
+        })?;
+
+        Ok(None)
+    }
+
+    /// Receives the fees collected by the gateway for the subnet.
+    ///
+    /// Fees are added to the reward pool or to the treasury of the
+    /// subnet, depending on the fee configuration of the subnet. They
+    /// are only requested from the gateway with the `gateway-extensions`
+    /// feature.
+    pub fn collect_fees<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        let st: State = rt.state()?;
+        rt.validate_immediate_caller_is(std::iter::once(&st.ipc_gateway_addr))?;
+
+        let amount = rt.message().value_received();
+        if amount == TokenAmount::zero() {
+            return Err(actor_error!(illegal_argument, "no fees sent"));
+        }
+
+        rt.transaction(|st: &mut State, _| {
+            // the reward pool and the treasury are paid out on termination
+            if matches!(st.status, Status::Terminating | Status::Killed) {
+                return Err(actor_error!(
+                    illegal_state,
+                    "cannot collect fees for a subnet that is terminating or killed"
+                ));
+            }
+
+            st.collected_fees += &amount;
+            match st.fee_config.destination {
+                FeeDestination::RewardPool => st.reward_pool += &amount,
+                FeeDestination::Treasury => st.treasury += &amount,
+            }
+
+            Ok(true)
+        })?;
+
+        Ok(None)
+    }
+
+    /// Adds the value sent to the treasury of the subnet.
+    pub fn donate<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let amount = rt.message().value_received();
+        if amount == TokenAmount::zero() {
+            return Err(actor_error!(illegal_argument, "no funds sent"));
+        }
+
+        rt.transaction(|st: &mut State, _| {
+            if matches!(st.status, Status::Terminating | Status::Killed) {
+                return Err(actor_error!(
+                    illegal_state,
+                    "cannot donate to a subnet that is terminating or killed"
+                ));
+            }
+
+            st.treasury += &amount;
+
+            Ok(true)
+        })?;
+
+        Ok(None)
+    }
+
+    /// Votes a spend of the treasury of the subnet.
+    ///
+    /// The funds are sent once validators holding a majority of the
+    /// voting power have proposed the same spend.
+    pub fn propose_spend<BS, RT>(
+        rt: &mut RT,
+        params: SpendParams,
+    ) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let caller = rt.message().caller();
+        let approved = rt.transaction(|st: &mut State, rt| {
+            if matches!(st.status, Status::Terminating | Status::Killed) {
+                return Err(actor_error!(
+                    illegal_state,
+                    "cannot spend the treasury of a subnet that is terminating or killed"
+                ));
+            }
+            if !st.is_validator(&caller) {
+                return Err(actor_error!(
+                    forbidden,
+                    "only validators can propose spends"
+                ));
+            }
+
+            st.vote_spend(rt.store(), &caller, &params)
+        })?;
+
+        if approved {
+            rt.send(params.to, METHOD_SEND, RawBytes::default(), params.amount)?;
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the collateral of the subnet as seen locally and as recorded
+    /// by the gateway.
+    pub fn collateral_status<BS, RT>(rt: &mut RT) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let st: State = rt.state()?;
+        Ok(Some(RawBytes::serialize(st.collateral_status())?))
+    }
+
+    /// Submits a proof of a validator signing two conflicting checkpoints.
+    ///
+    /// The stake of the validator is slashed. When built with the `gateway-extensions`
+    /// feature, if the validator signed a checkpoint committed within the challenge window,
+    /// that checkpoint and the ones built on top of it are also reverted in the subnet
+    /// and in the gateway, and all its signers are slashed. Checkpoints are never
+    /// reverted otherwise, as the upstream gateway can't revert child checkpoints yet.
+    pub fn submit_fraud_proof<BS, RT>(
+        rt: &mut RT,
+        proof: FraudProof,
+    ) -> Result<Option<RawBytes>, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let state: State = rt.state()?;
+        if state.status == Status::Terminating || state.status == Status::Killed {
+            return Err(actor_error!(
+                illegal_state,
+                "cannot submit fraud proofs to a subnet that is terminating or killed"
+            ));
+        }
+        let subnet = SubnetID::new(&state.parent_id, rt.message().receiver());
+        let window = proof.first.epoch();
+
+        if proof.second.epoch() != window
+            || *proof.first.source() != subnet
+            || *proof.second.source() != subnet
+        {
+            return Err(actor_error!(
+                illegal_argument,
+                "fraud proof checkpoints are not from the same window of the subnet"
+            ));
+        }
+        if proof.first.cid() == proof.second.cid() {
+            return Err(actor_error!(
+                illegal_argument,
+                "fraud proof checkpoints are not conflicting"
+            ));
+        }
+
+        let stake = state
+            .get_stake(rt.store(), &proof.signer)
+            .map_err(|e| e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "failed to load stake"))?
+            .unwrap_or_else(TokenAmount::zero);
+        if stake == TokenAmount::zero() {
+            return Err(actor_error!(
+                illegal_argument,
+                "signer has no stake to slash"
+            ));
+        }
+
+        let commit = state.get_commit(rt.store(), &window).map_err(|e| {
+            e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "failed to load commit info")
+        })?;
+        let epoch = rt.curr_epoch();
+        if let Some(c) = &commit {
+            if epoch > c.epoch + state.challenge_window {
+                return Err(actor_error!(
+                    illegal_state,
+                    "the challenge window of the checkpoint has expired"
+                ));
+            }
+        }
+
+        let keys = match state.validator_set.iter().find(|v| v.addr == proof.signer) {
+            Some(v) => v.signing_keys(epoch),
+            None => vec![resolve_key(rt, &proof.signer)?],
+        };
+        for ch in [&proof.first, &proof.second] {
+            state
+                .verify_checkpoint_signature(rt, &keys, ch)
+                .map_err(|e| {
+                    e.downcast_default(ExitCode::USR_ILLEGAL_ARGUMENT, "invalid signature")
+                })?;
+        }
+
+        rt.transaction(|st: &mut State, rt| {
+            // revert the checkpoint if the signer contributed to its commitment,
+            // slashing everyone that signed it.
+            #[cfg(feature = "gateway-extensions")]
+            let slashed_signers = {
+                let mut signers = vec![proof.signer];
+                if let Some(c) = commit
+                    .as_ref()
+                    .filter(|c| c.signers.contains(&proof.signer))
+                {
+                    let reverted = st
+                        .get_checkpoint(rt.store(), &window)
+                        .map_err(|e| {
+                            e.downcast_default(
+                                ExitCode::USR_ILLEGAL_STATE,
+                                "failed to load checkpoint",
+                            )
+                        })?
+                        .ok_or_else(|| {
+                            actor_error!(illegal_state, "committed checkpoint not found")
+                        })?;
+                    st.outbox.push(ext::gateway::revert_child_checkpoint(
+                        st.ipc_gateway_addr,
+                        &reverted,
+                    )?);
+                    st.revert_checkpoints(rt.store(), window).map_err(|e| {
+                        e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot revert checkpoints")
+                    })?;
+                    signers.extend(c.signers.iter().filter(|s| **s != proof.signer));
+                }
+                signers
+            };
+            // checkpoints committed in the gateway can't be reverted
+            #[cfg(not(feature = "gateway-extensions"))]
+            let slashed_signers = vec![proof.signer];
+
+            let mut slashed = TokenAmount::zero();
+            for signer in slashed_signers {
+                slashed += st.slash_stake(rt.store(), &signer).map_err(|e| {
+                    e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot slash stake")
+                })?;
+            }
+
+            update_status(st, rt.store(), epoch)?;
+
+            // release the stake from the gateway and burn it
+            st.outbox.push(ext::gateway::release_stake(
+                st.ipc_gateway_addr,
+                slashed.clone(),
+            )?);
+            st.outbox.push(burn(slashed));
+
+            Ok(true)
+        })?;
+
+        deliver_outbox(rt)?;
+
+        Ok(None)
+    }
+}
+
+/// Checks that the caller is an account actor or an actor whose code
+/// has been allowed to join the subnet as a validator.
+///
+/// It returns true if the caller is an account actor.
+fn check_validator_caller<BS, RT>(rt: &RT, st: &State, caller: &Address) -> Result<bool, ActorError>
+where
+    BS: Blockstore,
+    RT: Runtime<BS>,
+{
+    let code_cid = rt
+        .get_actor_code_cid(caller)
+        .ok_or_else(|| actor_error!(forbidden, "no code for caller {}", caller))?;
+    if rt.resolve_builtin_actor_type(&code_cid) == Some(Type::Account) {
+        return Ok(true);
+    }
+    if st.allowed_validator_codes.contains(&code_cid) {
+        return Ok(false);
+    }
+    Err(actor_error!(
+        forbidden,
+        "caller {} is not an account or an allowed actor type",
+        caller
+    ))
+}
+
+/// Returns the validator a checkpoint is submitted for.
+///
+/// Validators can submit checkpoints themselves or from the account of
+/// their signing key, so the key they stake with can be kept offline.
+/// Signing keys are unique across validators, so the key of the caller
+/// identifies a single validator.
+fn checkpoint_submitter<BS, RT>(
+    rt: &mut RT,
+    st: &State,
+    caller: &Address,
+) -> Result<Address, ActorError>
+where
+    BS: Blockstore,
+    RT: Runtime<BS>,
+{
+    if st.is_validator(caller) {
+        return Ok(*caller);
+    }
+
+    let epoch = rt.curr_epoch();
+    let key =
+        resolve_secp_bls(rt, caller).map_err(|_| actor_error!(illegal_state, "not validator"))?;
+    st.validator_set
+        .iter()
+        .find(|v| v.signing_keys(epoch).contains(&key))
+        .map(|v| v.addr)
+        .ok_or_else(|| actor_error!(illegal_state, "not validator"))
+}
+
+/// Checks that a signing key is a secp256k1 or BLS address, and that
+/// `validator` holds it by verifying its signature of the address.
+fn check_signing_key<BS, RT>(
+    rt: &mut RT,
+    validator: &Address,
+    params: &SigningKeyParams,
+) -> Result<(), ActorError>
+where
+    BS: Blockstore,
+    RT: Runtime<BS>,
+{
+    match params.key.protocol() {
+        Protocol::Secp256k1 | Protocol::BLS => {}
+        _ => {
+            return Err(actor_error!(
+                illegal_argument,
+                "signing key {} is not a secp256k1 or BLS address",
+                params.key
+            ));
+        }
+    }
+    rt.verify_signature(&params.proof, &params.key, &validator.to_bytes())
+        .map_err(|_| {
+            actor_error!(
+                illegal_argument,
+                "invalid proof of possession of signing key {}",
+                params.key
+            )
+        })
+}
+
+/// Resolves the public key address of an account.
+fn resolve_key<BS, RT>(rt: &mut RT, addr: &Address) -> Result<Address, ActorError>
+where
+    BS: Blockstore,
+    RT: Runtime<BS>,
+{
+    resolve_secp_bls(rt, addr)
+        .map_err(|e| actor_error!(illegal_argument, "cannot resolve key of {}: {}", addr, e))
+}
+
+/// Sends the messages pending in the outbox to the gateway in order.
+///
+/// Delivery stops at the first message that fails, which is kept in the
+/// outbox together with the ones after it so they can be retried with
+/// `FlushOutbox` without reverting the call that queued them. Changes of
+/// the stake that keep failing for `OUTBOX_RETRY_PERIOD` are dropped.
+fn deliver_outbox<BS, RT>(rt: &mut RT) -> Result<(), ActorError>
+where
+    BS: Blockstore,
+    RT: Runtime<BS>,
+{
+    let epoch = rt.curr_epoch();
+    loop {
+        let st: State = rt.state()?;
+        if st.outbox.is_empty() {
+            return Ok(());
+        }
+        let mut delivered = 0;
+        for p in st.outbox.iter() {
+            if rt
+                .send(p.to, p.method, p.params.clone(), p.value.clone())
+                .is_err()
+            {
+                break;
+            }
+            delivered += 1;
+        }
+
+        // retry the rest of the outbox if the failed message was dropped
+        let retry = rt.transaction(|st: &mut State, _| {
+            st.confirm_delivered(delivered)?;
+            Ok(!st.outbox.is_empty() && st.outbox_failed(epoch))
+        })?;
+        if !retry {
+            return Ok(());
+        }
+    }
+}
+
+/// Burns funds held by the actor.
+///
+/// Burns of stake are queued in the outbox after the release of the
+/// stake, so they are only sent once the gateway returned the funds.
+fn burn(amount: TokenAmount) -> CrossActorPayload {
+    CrossActorPayload::new(
+        *BURNT_FUNDS_ACTOR_ADDR,
+        METHOD_SEND,
+        RawBytes::default(),
+        amount,
+    )
+}
+
+/// Updates the status of the subnet, starting its termination if it
+/// stayed inactive for longer than `INACTIVE_GRACE_PERIOD`.
+fn update_status<BS: Blockstore>(
+    st: &mut State,
+    store: &BS,
+    epoch: ChainEpoch,
+) -> Result<(), ActorError> {
+    let prev_status = st.status;
+    st.mutate_state(store, epoch).map_err(|e| {
+        e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot update subnet status")
+    })?;
+    if prev_status != Status::Terminating && st.status == Status::Terminating {
+        terminate(st);
+    }
+    Ok(())
+}
+
+/// Moves the subnet to `Terminating`, queueing its unregistration from the
+/// gateway followed by the payout of the treasury and the reward pool.
+fn terminate(st: &mut State) {
+    st.status = Status::Terminating;
+    st.inactive_since = None;
+    st.outbox.push(ext::gateway::kill(st.ipc_gateway_addr));
+    let payout = treasury_payout(st);
+    st.outbox.extend(payout);
+}
+
+/// Empties the treasury and the reward pool of a subnet that is being terminated,
+/// returning the message that pays them to the beneficiary, or burns them if there
+/// is none. Rewards already distributed can still be claimed.
+fn treasury_payout(st: &mut State) -> Option<CrossActorPayload> {
+    let amount = std::mem::replace(&mut st.treasury, TokenAmount::zero())
+        + std::mem::replace(&mut st.reward_pool, TokenAmount::zero());
+    if amount == TokenAmount::zero() {
+        return None;
+    }
+
+    let to = st.treasury_beneficiary.unwrap_or(*BURNT_FUNDS_ACTOR_ADDR);
+    Some(CrossActorPayload::new(
+        to,
+        METHOD_SEND,
+        RawBytes::default(),
+        amount,
+    ))
+}
+
+impl ActorCode for Actor {
+    fn invoke_method<BS, RT>(
+        rt: &mut RT,
+        method: MethodNum,
+        params: &RawBytes,
+    ) -> Result<RawBytes, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        match FromPrimitive::from_u64(method) {
+            Some(Method::Constructor) => {
+                Self::constructor(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::default())
+            }
+            Some(Method::Join) => {
+                let res = Self::join(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::Leave) => {
+                let res = Self::leave(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::Kill) => {
+                let res = Self::kill(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::SubmitCheckpoint) => {
+                let res = Self::submit_checkpoint(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::FinalizeKill) => {
+                let res = Self::finalize_kill(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::GenesisCid) => {
+                let res = Self::genesis_cid(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::SubmitFraudProof) => {
+                let res = Self::submit_fraud_proof(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::LivenessStats) => {
+                let res = Self::liveness_stats(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::FundRewards) => {
+                let res = Self::fund_rewards(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::ClaimRewards) => {
+                let res = Self::claim_rewards(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::Delegate) => {
+                let res = Self::delegate(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::Undelegate) => {
+                let res = Self::undelegate(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::WithdrawUnbonded) => {
+                let res = Self::withdraw_unbonded(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::SetCommission) => {
+                let res = Self::set_commission(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::RotateSigningKey) => {
+                let res = Self::rotate_signing_key(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::SigningKeyHistory) => {
+                let res = Self::signing_key_history(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::UpdateAllowList) => {
+                let res = Self::update_allow_list(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::AllowList) => {
+                let res = Self::allow_list(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::ProposeRemoval) => {
+                let res = Self::propose_removal(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::FlushOutbox) => {
+                let res = Self::flush_outbox(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::CollateralStatus) => {
+                let res = Self::collateral_status(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::SyncStake) => {
+                let res = Self::sync_stake(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            #[cfg(feature = "gateway-extensions")]
+            Some(Method::NotifyFund) => {
+                let res = Self::notify_fund(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::CollectFees) => {
+                let res = Self::collect_fees(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::Donate) => {
+                let res = Self::donate(rt)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::ProposeSpend) => {
+                let res = Self::propose_spend(rt, cbor::deserialize_params(params)?)?;
                 Ok(RawBytes::serialize(res)?)
             }
             None => Err(actor_error!(unhandled_message; "Invalid method")),
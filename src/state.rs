@@ -1,8 +1,7 @@
 use anyhow::anyhow;
 use cid::Cid;
-use fil_actors_runtime::runtime::fvm::resolve_secp_bls;
 use fil_actors_runtime::runtime::Runtime;
-use fil_actors_runtime::{actor_error, ActorError};
+use fil_actors_runtime::{actor_error, ActorDowncast, ActorError};
 use fvm_ipld_blockstore::Blockstore;
 use fvm_ipld_encoding::{Cbor, RawBytes};
 use fvm_ipld_hamt::BytesKey;
@@ -10,13 +9,16 @@ use fvm_shared::address::Address;
 use fvm_shared::bigint::Zero;
 use fvm_shared::clock::ChainEpoch;
 use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ExitCode;
 use ipc_gateway::{Checkpoint, SubnetID, DEFAULT_CHECKPOINT_PERIOD, MIN_COLLATERAL_AMOUNT};
 use lazy_static::lazy_static;
 use num::rational::Ratio;
 use num::BigInt;
 use primitives::{TCid, THamt};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
+use crate::ext;
 use crate::types::*;
 
 lazy_static! {
@@ -35,12 +37,77 @@ pub struct State {
     pub consensus: ConsensusType,
     pub min_validator_stake: TokenAmount,
     pub total_stake: TokenAmount,
+    /// Value funded into the subnet that hasn't been released
+    /// bottom-up yet. It's only tracked with the `gateway-extensions`
+    /// feature, since it relies on the gateway notifying funds.
+    pub circ_supply: TokenAmount,
     pub stake: TCid<THamt<Cid, TokenAmount>>,
+    /// Stake delegated to each validator.
+    pub delegations: TCid<THamt<Cid, Vec<Delegation>>>,
+    pub total_delegated: TokenAmount,
+    pub unbonding: TCid<THamt<Cid, Vec<Unbonding>>>,
+    /// Epoch from which validators ejected from the validator
+    /// set can join the subnet again.
+    pub rejoin_epochs: TCid<THamt<Cid, ChainEpoch>>,
     pub status: Status,
+    pub inactive_since: Option<ChainEpoch>,
+    pub unregistered: bool,
     pub genesis: Vec<u8>,
+    pub genesis_cid: Cid,
     pub finality_threshold: ChainEpoch,
     pub check_period: ChainEpoch,
     pub checkpoints: TCid<THamt<Cid, Checkpoint>>,
+    pub commits: TCid<THamt<Cid, CommitInfo>>,
+    pub challenge_window: ChainEpoch,
+    pub last_committed_epoch: ChainEpoch,
+    pub missed_windows: u64,
+    pub max_missed_windows: u64,
+    pub participation_policy: ParticipationPolicy,
+    /// Funds available to reward checkpoint signers.
+    pub reward_pool: TokenAmount,
+    pub checkpoint_reward: TokenAmount,
+    /// Rewards distributed but not claimed yet.
+    pub unclaimed_rewards: TokenAmount,
+    pub rewards: TCid<THamt<Cid, TokenAmount>>,
+    pub leaving_penalty: LeavingPenalty,
+    pub allowed_validator_codes: Vec<Cid>,
+    pub key_rotation_windows: u64,
+    /// Rotations of the signing keys of each validator.
+    pub key_history: TCid<THamt<Cid, Vec<KeyRotation>>>,
+    pub permissioned: bool,
+    pub owner: Option<Address>,
+    pub allow_list: Vec<Address>,
+    /// Votes of validators on updates of the allow-list,
+    /// keyed by the CID of the update.
+    pub allow_list_votes: TCid<THamt<Cid, Votes>>,
+    /// Votes of validators on the removal of a validator,
+    /// keyed by the address of the validator.
+    pub removal_votes: TCid<THamt<Cid, Votes>>,
+    /// Messages to the gateway waiting to be delivered, along with the
+    /// burns of the stake released by them and the payout of the treasury
+    /// following the kill of the subnet.
+    pub outbox: Vec<CrossActorPayload>,
+    /// Epoch since which the message at the head of the outbox fails.
+    pub outbox_stalled_since: Option<ChainEpoch>,
+    /// Number of messages dropped from the outbox after failing
+    /// for longer than `OUTBOX_RETRY_PERIOD`.
+    pub dropped_messages: u64,
+    /// Collateral recorded by the gateway for the subnet according
+    /// to the messages delivered to it.
+    pub gateway_collateral: TokenAmount,
+    /// Stake held by the subnet that never reached the gateway
+    /// because the message forwarding it was dropped.
+    pub unforwarded_stake: TokenAmount,
+    pub fee_config: FeeConfig,
+    /// Total fees collected by the subnet.
+    pub collected_fees: TokenAmount,
+    /// Operational funds of the subnet, not considered collateral.
+    pub treasury: TokenAmount,
+    /// Address the treasury and the reward pool are returned to when the subnet is killed.
+    pub treasury_beneficiary: Option<Address>,
+    /// Votes of validators on spends of the treasury,
+    /// keyed by the CID of the spend.
+    pub spend_votes: TCid<THamt<Cid, Votes>>,
     pub window_checks: TCid<THamt<Cid, Votes>>,
     pub validator_set: Vec<Validator>,
     pub min_validators: u64,
@@ -54,13 +121,37 @@ impl Cbor for State {}
 impl State {
     pub fn new<BS: Blockstore>(store: &BS, params: ConstructParams) -> anyhow::Result<State> {
         let min_stake = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        let check_period = if params.check_period < DEFAULT_CHECKPOINT_PERIOD {
+            DEFAULT_CHECKPOINT_PERIOD
+        } else {
+            params.check_period
+        };
+
+        let genesis = Genesis::unmarshal_cbor(&params.genesis)
+            .map_err(|e| actor_error!(illegal_argument, "failed to parse genesis: {}", e))?;
+        genesis
+            .validate(params.consensus, check_period)
+            .map_err(|e| actor_error!(illegal_argument, "invalid genesis: {}", e))?;
+        params
+            .leaving_penalty
+            .validate()
+            .map_err(|e| actor_error!(illegal_argument, "invalid leaving penalty: {}", e))?;
+        params
+            .participation_policy
+            .validate()
+            .map_err(|e| actor_error!(illegal_argument, "invalid participation policy: {}", e))?;
+        params
+            .fee_config
+            .validate()
+            .map_err(|e| actor_error!(illegal_argument, "invalid fee config: {}", e))?;
 
-        let state = State {
+        let mut state = State {
             name: params.name,
             parent_id: params.parent,
             ipc_gateway_addr: Address::new_id(params.ipc_gateway_addr),
             consensus: params.consensus,
             total_stake: TokenAmount::zero(),
+            circ_supply: TokenAmount::zero(),
             min_validator_stake: if params.min_validator_stake < min_stake {
                 min_stake
             } else {
@@ -68,19 +159,93 @@ impl State {
             },
             min_validators: params.min_validators,
             finality_threshold: params.finality_threshold,
-            check_period: if params.check_period < DEFAULT_CHECKPOINT_PERIOD {
-                DEFAULT_CHECKPOINT_PERIOD
-            } else {
-                params.check_period
-            },
+            check_period,
+            genesis_cid: genesis.cid()?,
             genesis: params.genesis,
             status: Status::Instantiated,
+            inactive_since: None,
+            unregistered: false,
             checkpoints: TCid::new_hamt(store)?,
+            commits: TCid::new_hamt(store)?,
+            challenge_window: params.challenge_window,
+            last_committed_epoch: 0,
+            missed_windows: 0,
+            max_missed_windows: params.max_missed_windows,
+            participation_policy: params.participation_policy,
+            reward_pool: TokenAmount::zero(),
+            checkpoint_reward: params.checkpoint_reward,
+            unclaimed_rewards: TokenAmount::zero(),
+            rewards: TCid::new_hamt(store)?,
+            leaving_penalty: params.leaving_penalty,
+            allowed_validator_codes: params.allowed_validator_codes,
+            key_rotation_windows: params.key_rotation_windows,
+            key_history: TCid::new_hamt(store)?,
+            permissioned: params.permissioning.is_some(),
+            owner: params.permissioning.as_ref().and_then(|p| p.owner),
+            allow_list: params
+                .permissioning
+                .map(|p| p.allow_list)
+                .unwrap_or_default(),
+            allow_list_votes: TCid::new_hamt(store)?,
+            removal_votes: TCid::new_hamt(store)?,
+            outbox: Vec::new(),
+            outbox_stalled_since: None,
+            dropped_messages: 0,
+            gateway_collateral: TokenAmount::zero(),
+            unforwarded_stake: TokenAmount::zero(),
+            fee_config: params.fee_config,
+            collected_fees: TokenAmount::zero(),
+            treasury: TokenAmount::zero(),
+            treasury_beneficiary: params.treasury_beneficiary,
+            spend_votes: TCid::new_hamt(store)?,
             stake: TCid::new_hamt(store)?,
+            delegations: TCid::new_hamt(store)?,
+            total_delegated: TokenAmount::zero(),
+            unbonding: TCid::new_hamt(store)?,
+            rejoin_epochs: TCid::new_hamt(store)?,
             window_checks: TCid::new_hamt(store)?,
             validator_set: Vec::new(),
         };
 
+        // seed the initial validators of the subnet.
+        let mut seen = HashSet::new();
+        for v in &params.initial_validators {
+            if !seen.insert(v.addr) {
+                return Err(actor_error!(
+                    illegal_argument,
+                    "duplicate initial validator: {}",
+                    v.addr
+                )
+                .into());
+            }
+            if !genesis.validators.contains(v) {
+                return Err(actor_error!(
+                    illegal_argument,
+                    "initial validator {} not included in genesis",
+                    v.addr
+                )
+                .into());
+            }
+            if !state.is_allowed(&v.addr) {
+                return Err(actor_error!(
+                    illegal_argument,
+                    "initial validator {} not in the allow-list",
+                    v.addr
+                )
+                .into());
+            }
+            if v.stake < state.min_validator_stake {
+                return Err(actor_error!(
+                    illegal_argument,
+                    "initial validator {} has less than the minimum stake",
+                    v.addr
+                )
+                .into());
+            }
+            // the key of initial validators is resolved by the constructor
+            state.add_stake(store, &v.addr, &v.addr, &v.net_addr, &v.stake)?;
+        }
+
         Ok(state)
     }
 
@@ -131,6 +296,235 @@ impl State {
         Ok(())
     }
 
+    /// Checks if an address is allowed to join the subnet as a validator.
+    pub fn is_allowed(&self, addr: &Address) -> bool {
+        !self.permissioned || self.allow_list.contains(addr)
+    }
+
+    /// Adds or removes an address from the allow-list. Removed validators
+    /// keep their stake but can't increase it.
+    pub fn update_allow_list(&mut self, params: &AllowListParams) {
+        let pos = self.allow_list.iter().position(|a| *a == params.addr);
+        match (params.allowed, pos) {
+            (true, None) => self.allow_list.push(params.addr),
+            (false, Some(i)) => {
+                self.allow_list.remove(i);
+            }
+            _ => {}
+        }
+    }
+
+    /// Records the vote of a validator on an update of the allow-list, and
+    /// applies the update once it reaches a majority of the voting power.
+    ///
+    /// Returns whether the update has been applied.
+    pub fn vote_allow_list<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        validator: &Address,
+        params: &AllowListParams,
+    ) -> Result<bool, ActorError> {
+        let cid = params
+            .cid()
+            .map_err(|_| actor_error!(illegal_argument, "cannot compute cid of update"))?;
+        let applied = self.tally_vote(
+            store,
+            |st| &mut st.allow_list_votes,
+            BytesKey::from(cid.to_bytes()),
+            validator,
+        )?;
+        if applied {
+            self.update_allow_list(params);
+        }
+
+        Ok(applied)
+    }
+
+    /// Records the vote of a validator to remove `target` from the validator set.
+    ///
+    /// Once the removal reaches a majority of the voting power, the target is
+    /// ejected and its stake moved to unbonding. Returns the stake of the
+    /// target if it has been ejected.
+    pub fn vote_removal<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        validator: &Address,
+        target: &Address,
+        curr_epoch: ChainEpoch,
+    ) -> Result<Option<TokenAmount>, ActorError> {
+        let ejected = self.tally_vote(
+            store,
+            |st| &mut st.removal_votes,
+            BytesKey::from(target.to_bytes()),
+            validator,
+        )?;
+        if !ejected {
+            return Ok(None);
+        }
+
+        let stake = self
+            .eject_validator(store, target, curr_epoch)
+            .map_err(|e| {
+                e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot remove validator")
+            })?;
+
+        Ok(Some(stake))
+    }
+
+    /// Records the vote of a validator on a spend of the treasury, and
+    /// withdraws the amount from the treasury once the spend reaches a
+    /// majority of the voting power.
+    ///
+    /// Returns whether the spend has been approved.
+    pub fn vote_spend<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        validator: &Address,
+        params: &SpendParams,
+    ) -> Result<bool, ActorError> {
+        if params.amount <= TokenAmount::zero() || params.amount > self.treasury {
+            return Err(actor_error!(
+                illegal_argument,
+                "cannot spend {} from a treasury of {}",
+                params.amount,
+                self.treasury
+            ));
+        }
+
+        let cid = params
+            .cid()
+            .map_err(|_| actor_error!(illegal_argument, "cannot compute cid of spend"))?;
+        let approved = self.tally_vote(
+            store,
+            |st| &mut st.spend_votes,
+            BytesKey::from(cid.to_bytes()),
+            validator,
+        )?;
+        if approved {
+            self.treasury -= &params.amount;
+        }
+
+        Ok(approved)
+    }
+
+    /// Records the vote of a validator on a proposal tracked in `proposals`.
+    ///
+    /// Returns whether the proposal reached a majority of the voting power,
+    /// in which case its votes are cleared so it can be proposed again.
+    fn tally_vote<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        proposals: fn(&mut State) -> &mut TCid<THamt<Cid, Votes>>,
+        key: BytesKey,
+        validator: &Address,
+    ) -> Result<bool, ActorError> {
+        let hamt = proposals(self)
+            .load(store)
+            .map_err(|_| actor_error!(illegal_state, "cannot load proposal votes"))?;
+        let mut votes = hamt
+            .get(&key)
+            .map_err(|_| actor_error!(illegal_state, "cannot read votes"))?
+            .cloned()
+            .unwrap_or(Votes {
+                validators: Vec::new(),
+            });
+        if votes.validators.contains(validator) {
+            return Err(actor_error!(
+                illegal_state,
+                "validator has already voted the proposal"
+            ));
+        }
+        votes.validators.push(*validator);
+
+        let passed = self.has_majority_vote(store, &votes)?;
+        proposals(self)
+            .modify(store, |hamt| {
+                if passed {
+                    hamt.delete(&key)?;
+                } else {
+                    hamt.set(key, votes)?;
+                }
+                Ok(true)
+            })
+            .map_err(|_| actor_error!(illegal_state, "cannot modify proposal votes"))?;
+
+        Ok(passed)
+    }
+
+    /// Removes the first `delivered` messages from the outbox, updating
+    /// the collateral recorded by the gateway accordingly.
+    pub fn confirm_delivered(&mut self, delivered: usize) -> Result<(), ActorError> {
+        if delivered > 0 {
+            self.outbox_stalled_since = None;
+        }
+        let msgs: Vec<CrossActorPayload> = self.outbox.drain(..delivered).collect();
+        for p in msgs {
+            if p.to != self.ipc_gateway_addr {
+                continue;
+            }
+            let (added, released) = ext::gateway::collateral_change(&p)?;
+            self.gateway_collateral += added;
+            self.gateway_collateral -= released;
+            if p.method == ipc_gateway::Method::Kill as u64 {
+                self.unregistered = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records that the message at the head of the outbox failed to be delivered.
+    ///
+    /// Changes of the stake that keep failing for `OUTBOX_RETRY_PERIOD` epochs are
+    /// dropped, together with the burns queued after them, so they don't block the
+    /// rest of the outbox. They can be reconciled later with `SyncStake`. Any other
+    /// message, like the registration, the kill or the commitment of checkpoints,
+    /// is kept until it's delivered. It returns whether the message was dropped.
+    pub fn outbox_failed(&mut self, curr_epoch: ChainEpoch) -> bool {
+        let since = match self.outbox_stalled_since {
+            Some(since) => since,
+            None => {
+                self.outbox_stalled_since = Some(curr_epoch);
+                return false;
+            }
+        };
+        let gateway = self.ipc_gateway_addr;
+        let head = &self.outbox[0];
+        let is_add = head.method == ipc_gateway::Method::AddStake as u64;
+        let is_release = head.method == ipc_gateway::Method::ReleaseStake as u64;
+        if head.to != gateway || !(is_add || is_release) {
+            return false;
+        }
+        if curr_epoch < since + OUTBOX_RETRY_PERIOD {
+            return false;
+        }
+
+        if is_add {
+            self.unforwarded_stake += &head.value;
+        }
+        let burns = self.outbox[1..]
+            .iter()
+            .take_while(|p| p.to != gateway)
+            .count();
+        self.outbox.drain(..1 + burns);
+        self.outbox_stalled_since = None;
+        self.dropped_messages += 1;
+        true
+    }
+
+    /// Compares the collateral of the subnet with the one recorded by the gateway.
+    pub fn collateral_status(&self) -> CollateralStatus {
+        let local = self.total_stake.clone() + &self.total_delegated;
+        let registered = matches!(self.status, Status::Active | Status::Inactive);
+        let consistent = !registered || !self.outbox.is_empty() || local == self.gateway_collateral;
+        CollateralStatus {
+            local,
+            gateway: self.gateway_collateral.clone(),
+            pending_messages: self.outbox.len() as u64,
+            dropped_messages: self.dropped_messages,
+            consistent,
+        }
+    }
+
     /// Get the stake of an address.
     pub fn get_stake<BS: Blockstore>(
         &self,
@@ -147,6 +541,7 @@ impl State {
         &mut self,
         store: &BS,
         addr: &Address,
+        key_addr: &Address,
         net_addr: &str,
         amount: &TokenAmount,
     ) -> anyhow::Result<()> {
@@ -166,11 +561,19 @@ impl State {
 
             // check if the miner has collateral to become a validator
             if updated_stake >= self.min_validator_stake
+                && !self.is_validator(addr)
                 && (self.consensus != ConsensusType::Delegated || self.validator_set.is_empty())
             {
                 self.validator_set.push(Validator {
                     addr: *addr,
                     net_addr: String::from(net_addr),
+                    key_addr: *key_addr,
+                    pending_key: None,
+                    retiring_key: None,
+                    missed_windows: 0,
+                    participation: Vec::new(),
+                    commission: 0,
+                    commission_updated_at: None,
                 });
             }
 
@@ -192,8 +595,7 @@ impl State {
             // hamt, that means it's the first time adding stake and we just
             // give default stake amount 0.
             let key = BytesKey::from(addr.to_bytes());
-            let mut stake = hamt.get(&key)?.unwrap_or(&TokenAmount::zero()).clone();
-            stake = stake.div_floor(LEAVING_COEFF);
+            let stake = hamt.get(&key)?.unwrap_or(&TokenAmount::zero()).clone();
 
             if stake.lt(amount) {
                 return Err(anyhow!(format!(
@@ -218,6 +620,255 @@ impl State {
         Ok(())
     }
 
+    /// Charges the leaving penalty on the stake withdrawn by a validator
+    /// that has already been removed with `rm_stake`.
+    ///
+    /// The penalty is redistributed among the remaining validators proportionally
+    /// to their stake or burnt if there are none. It returns the amount to release
+    /// to the validator and the amount to burn.
+    pub fn charge_leaving_penalty<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        stake: &TokenAmount,
+    ) -> anyhow::Result<(TokenAmount, TokenAmount)> {
+        let penalty = self.leaving_penalty.apply(stake);
+        let released = stake.clone() - &penalty;
+        if penalty == TokenAmount::zero()
+            || self.leaving_penalty.destination == PenaltyDestination::Burn
+            || self.validator_set.is_empty()
+        {
+            return Ok((released, penalty));
+        }
+
+        let mut stakes = Vec::new();
+        let mut total = TokenAmount::zero();
+        for v in &self.validator_set {
+            let s = self
+                .get_stake(store, &v.addr)?
+                .unwrap_or_else(TokenAmount::zero);
+            total += &s;
+            stakes.push((v.addr, s));
+        }
+
+        self.stake.modify(store, |hamt| {
+            let mut left = penalty.clone();
+            for (i, (addr, s)) in stakes.iter().enumerate() {
+                // the remainder of the integer division goes to the last validator
+                let share = if i == stakes.len() - 1 {
+                    left.clone()
+                } else {
+                    TokenAmount::from_atto(penalty.atto().clone() * s.atto() / total.atto())
+                };
+                left -= &share;
+                hamt.set(BytesKey::from(addr.to_bytes()), s.clone() + share)?;
+            }
+            Ok(true)
+        })?;
+        self.total_stake += penalty;
+
+        Ok((released, TokenAmount::zero()))
+    }
+
+    /// Get the stake delegated to a validator.
+    pub fn get_delegations<BS: Blockstore>(
+        &self,
+        store: &BS,
+        validator: &Address,
+    ) -> anyhow::Result<Vec<Delegation>> {
+        let hamt = self.delegations.load(store)?;
+        let delegations = hamt.get(&BytesKey::from(validator.to_bytes()))?;
+        Ok(delegations.cloned().unwrap_or_default())
+    }
+
+    /// Stake of a validator including the stake delegated to it.
+    pub fn voting_power<BS: Blockstore>(
+        &self,
+        store: &BS,
+        addr: &Address,
+    ) -> anyhow::Result<TokenAmount> {
+        let stake = self
+            .get_stake(store, addr)?
+            .unwrap_or_else(TokenAmount::zero);
+        Ok(self
+            .get_delegations(store, addr)?
+            .iter()
+            .fold(stake, |acc, d| acc + &d.amount))
+    }
+
+    pub fn add_delegation<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        delegator: &Address,
+        validator: &Address,
+        amount: &TokenAmount,
+    ) -> anyhow::Result<()> {
+        let mut delegations = self.get_delegations(store, validator)?;
+        match delegations.iter_mut().find(|d| d.delegator == *delegator) {
+            Some(d) => d.amount += amount,
+            None => delegations.push(Delegation {
+                delegator: *delegator,
+                amount: amount.clone(),
+            }),
+        }
+        self.set_delegations(store, validator, delegations)?;
+
+        self.total_delegated += amount;
+        Ok(())
+    }
+
+    pub fn rm_delegation<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        delegator: &Address,
+        validator: &Address,
+        amount: &TokenAmount,
+    ) -> anyhow::Result<()> {
+        let mut delegations = self.get_delegations(store, validator)?;
+        let d = delegations
+            .iter_mut()
+            .find(|d| d.delegator == *delegator)
+            .ok_or_else(|| anyhow!("no stake delegated to {} by {}", validator, delegator))?;
+        if d.amount < *amount {
+            return Err(anyhow!(
+                "not enough stake delegated to {} by {}",
+                validator,
+                delegator
+            ));
+        }
+        d.amount -= amount;
+        delegations.retain(|d| d.amount > TokenAmount::zero());
+        self.set_delegations(store, validator, delegations)?;
+
+        self.total_delegated -= amount;
+        Ok(())
+    }
+
+    /// Moves all the stake delegated to a validator leaving the
+    /// validator set to unbonding, returning the amount undelegated.
+    pub fn undelegate_all<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        validator: &Address,
+        curr_epoch: ChainEpoch,
+    ) -> anyhow::Result<TokenAmount> {
+        let mut undelegated = TokenAmount::zero();
+        for d in self.get_delegations(store, validator)? {
+            self.add_unbonding(store, &d.delegator, &d.amount, curr_epoch)?;
+            undelegated += d.amount;
+        }
+        self.set_delegations(store, validator, Vec::new())?;
+
+        self.total_delegated -= &undelegated;
+        Ok(undelegated)
+    }
+
+    fn set_delegations<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        validator: &Address,
+        delegations: Vec<Delegation>,
+    ) -> anyhow::Result<()> {
+        self.delegations.modify(store, |hamt| {
+            let key = BytesKey::from(validator.to_bytes());
+            if delegations.is_empty() {
+                hamt.delete(&key)?;
+            } else {
+                hamt.set(key, delegations)?;
+            }
+            Ok(true)
+        })?;
+        Ok(())
+    }
+
+    /// Locks undelegated stake until the end of the unbonding period.
+    pub fn add_unbonding<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        delegator: &Address,
+        amount: &TokenAmount,
+        curr_epoch: ChainEpoch,
+    ) -> anyhow::Result<()> {
+        self.unbonding.modify(store, |hamt| {
+            let key = BytesKey::from(delegator.to_bytes());
+            let mut entries = hamt.get(&key)?.cloned().unwrap_or_default();
+            entries.push(Unbonding {
+                amount: amount.clone(),
+                release_epoch: curr_epoch + UNBONDING_PERIOD,
+            });
+            hamt.set(key, entries)?;
+            Ok(true)
+        })?;
+        Ok(())
+    }
+
+    /// Removes the unbonding entries of a delegator that matured,
+    /// returning the amount that can be withdrawn.
+    pub fn withdraw_unbonded<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        delegator: &Address,
+        curr_epoch: ChainEpoch,
+    ) -> anyhow::Result<TokenAmount> {
+        let mut amount = TokenAmount::zero();
+        self.unbonding.modify(store, |hamt| {
+            let key = BytesKey::from(delegator.to_bytes());
+            let (matured, pending): (Vec<Unbonding>, Vec<Unbonding>) = hamt
+                .get(&key)?
+                .cloned()
+                .unwrap_or_default()
+                .into_iter()
+                .partition(|u| u.release_epoch <= curr_epoch);
+            amount = matured
+                .iter()
+                .fold(TokenAmount::zero(), |acc, u| acc + &u.amount);
+            if pending.is_empty() {
+                hamt.delete(&key)?;
+            } else {
+                hamt.set(key, pending)?;
+            }
+            Ok(true)
+        })?;
+        Ok(amount)
+    }
+
+    /// Removes a validator from the validator set, moving all its stake and
+    /// the stake delegated to it to unbonding. The validator can't join the
+    /// subnet again for `REJOIN_COOLDOWN`.
+    ///
+    /// It returns the stake to release from the gateway.
+    pub fn eject_validator<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        addr: &Address,
+        curr_epoch: ChainEpoch,
+    ) -> anyhow::Result<TokenAmount> {
+        let stake = self
+            .get_stake(store, addr)?
+            .unwrap_or_else(TokenAmount::zero);
+        self.rm_stake(store, addr, &stake)?;
+        self.add_unbonding(store, addr, &stake, curr_epoch)?;
+        let undelegated = self.undelegate_all(store, addr, curr_epoch)?;
+        self.rejoin_epochs.modify(store, |hamt| {
+            hamt.set(
+                BytesKey::from(addr.to_bytes()),
+                curr_epoch + REJOIN_COOLDOWN,
+            )?;
+            Ok(true)
+        })?;
+        Ok(stake + undelegated)
+    }
+
+    /// Get the epoch from which an ejected validator can join the subnet again.
+    pub fn get_rejoin_epoch<BS: Blockstore>(
+        &self,
+        store: &BS,
+        addr: &Address,
+    ) -> anyhow::Result<Option<ChainEpoch>> {
+        let hamt = self.rejoin_epochs.load(store)?;
+        let epoch = hamt.get(&BytesKey::from(addr.to_bytes()))?;
+        Ok(epoch.copied())
+    }
+
     pub fn has_majority_vote<BS: Blockstore>(
         &self,
         store: &BS,
@@ -225,44 +876,137 @@ impl State {
     ) -> Result<bool, ActorError> {
         let mut sum = TokenAmount::zero();
         for v in &votes.validators {
-            let stake = self
-                .get_stake(store, v)
+            sum += self
+                .voting_power(store, v)
                 .map_err(|_| actor_error!(illegal_state, "cannot load stake from hamt"))?;
-            sum += stake.unwrap_or_else(TokenAmount::zero);
         }
-        let ftotal = Ratio::from_integer(self.total_stake.atto().clone());
+        let total = self.total_stake.clone() + &self.total_delegated;
+        let ftotal = Ratio::from_integer(total.atto().clone());
         Ok(Ratio::from_integer(sum.atto().clone()) / ftotal >= *VOTING_THRESHOLD)
     }
 
-    pub fn mutate_state(&mut self) {
+    /// Stake held by the members of the validator set.
+    pub fn validator_stake<BS: Blockstore>(&self, store: &BS) -> anyhow::Result<TokenAmount> {
+        let mut sum = TokenAmount::zero();
+        for v in &self.validator_set {
+            sum += self
+                .get_stake(store, &v.addr)?
+                .unwrap_or_else(TokenAmount::zero);
+        }
+        Ok(sum)
+    }
+
+    /// Updates the status of the subnet. Only the stake of
+    /// validators is taken into account for its activation.
+    pub fn mutate_state<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        curr_epoch: ChainEpoch,
+    ) -> anyhow::Result<()> {
+        let min_collateral = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        let stake = self.validator_stake(store)?;
         match self.status {
             Status::Instantiated => {
-                if self.total_stake >= TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT) {
-                    self.status = Status::Active
+                if stake >= min_collateral {
+                    self.status = Status::Active;
+                    self.start_checkpoint_windows(curr_epoch);
                 }
             }
             Status::Active => {
-                if self.total_stake < TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT) {
-                    self.status = Status::Inactive
+                if stake < min_collateral || self.liveness_exceeded(curr_epoch) {
+                    self.status = Status::Inactive;
+                    self.inactive_since = Some(curr_epoch);
                 }
             }
             Status::Inactive => {
-                if self.total_stake >= TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT) {
-                    self.status = Status::Active
-                }
-            }
-            // if no total_stake and current_balance left (except if we are testing where the funds
-            // are never leaving the actor)
-            Status::Terminating => {
-                if self.total_stake == TokenAmount::zero() {
-                    self.status = Status::Killed
+                if stake >= min_collateral && !self.liveness_exceeded(curr_epoch) {
+                    self.status = Status::Active;
+                    self.inactive_since = None;
+                    self.start_checkpoint_windows(curr_epoch);
+                } else if self.inactive_grace_expired(curr_epoch) {
+                    // the subnet didn't recover in time, start its termination.
+                    self.status = Status::Terminating;
+                    self.inactive_since = None;
                 }
             }
+            // terminating subnets are only moved to `Killed` through an
+            // explicit `FinalizeKill` once the gateway confirmed the unregistration.
             _ => {}
         }
+        Ok(())
+    }
+
+    /// Windows before the (re)activation of the subnet are not
+    /// considered missed.
+    fn start_checkpoint_windows(&mut self, curr_epoch: ChainEpoch) {
+        let window = curr_epoch - curr_epoch % self.check_period;
+        if window > self.last_committed_epoch {
+            self.last_committed_epoch = window;
+        }
     }
 
-    fn get_checkpoint<BS: Blockstore>(
+    /// Number of consecutive checkpoint windows since the last commitment
+    /// whose submission deadline has passed.
+    ///
+    /// The deadline to commit a window is the start of the next one.
+    pub fn consecutive_missed_windows(&self, curr_epoch: ChainEpoch) -> u64 {
+        let elapsed = (curr_epoch - self.last_committed_epoch) / self.check_period;
+        if elapsed > 1 {
+            (elapsed - 1) as u64
+        } else {
+            0
+        }
+    }
+
+    fn liveness_exceeded(&self, curr_epoch: ChainEpoch) -> bool {
+        self.max_missed_windows != 0
+            && self.consecutive_missed_windows(curr_epoch) >= self.max_missed_windows
+    }
+
+    /// Returns true if checkpoints can be submitted to the subnet.
+    ///
+    /// Subnets that are inactive because they missed too many windows but whose
+    /// validators still hold enough collateral can recover by committing checkpoints.
+    pub fn accepts_checkpoints<BS: Blockstore>(&self, store: &BS) -> anyhow::Result<bool> {
+        Ok(self.status == Status::Active
+            || (self.status == Status::Inactive
+                && self.validator_stake(store)? >= TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT)))
+    }
+
+    pub fn liveness_stats(&self, curr_epoch: ChainEpoch) -> LivenessStats {
+        LivenessStats {
+            last_committed_epoch: self.last_committed_epoch,
+            missed_windows: self.missed_windows,
+            consecutive_missed_windows: self.consecutive_missed_windows(curr_epoch),
+            validators: self
+                .validator_set
+                .iter()
+                .map(|v| ValidatorLiveness {
+                    addr: v.addr,
+                    missed_windows: v.missed_windows,
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns true if the subnet can be moved to `Killed`.
+    pub fn can_finalize_kill(&self) -> bool {
+        self.status == Status::Terminating
+            && self.unregistered
+            && self.total_stake == TokenAmount::zero()
+            && self.total_delegated == TokenAmount::zero()
+    }
+
+    /// Returns true if the subnet has been inactive for longer than
+    /// the grace period.
+    pub fn inactive_grace_expired(&self, curr_epoch: ChainEpoch) -> bool {
+        self.status == Status::Inactive
+            && self
+                .inactive_since
+                .is_some_and(|since| curr_epoch >= since + INACTIVE_GRACE_PERIOD)
+    }
+
+    pub fn get_checkpoint<BS: Blockstore>(
         &self,
         store: &BS,
         epoch: &ChainEpoch,
@@ -278,18 +1022,57 @@ impl State {
         Ok(checkpoint)
     }
 
+    pub fn get_commit<BS: Blockstore>(
+        &self,
+        store: &BS,
+        epoch: &ChainEpoch,
+    ) -> anyhow::Result<Option<CommitInfo>> {
+        let hamt = self.commits.load(store)?;
+        let commit = hamt.get(&BytesKey::from(epoch.to_ne_bytes().to_vec()))?;
+        Ok(commit.cloned())
+    }
+
     pub fn is_validator(&self, addr: &Address) -> bool {
         self.validator_set.iter().any(|x| x.addr == *addr)
     }
 
+    /// Checks that `key` isn't a signing key of any validator other
+    /// than `addr`, so checkpoint signatures can only be attributed
+    /// to a single validator.
+    pub fn check_key_unused(
+        &self,
+        key: &Address,
+        addr: &Address,
+        curr_epoch: ChainEpoch,
+    ) -> Result<(), ActorError> {
+        match self
+            .validator_set
+            .iter()
+            .find(|v| v.addr != *addr && v.holds_key(key, curr_epoch))
+        {
+            Some(v) => Err(actor_error!(
+                illegal_argument,
+                "{} is already a signing key of validator {}",
+                key,
+                v.addr
+            )),
+            None => Ok(()),
+        }
+    }
+
     /// Do not call this function in transaction
-    pub fn verify_checkpoint<BS, RT>(&self, rt: &mut RT, ch: &Checkpoint) -> anyhow::Result<()>
+    pub fn verify_checkpoint<BS, RT>(
+        &self,
+        rt: &mut RT,
+        ch: &Checkpoint,
+        validator: &Address,
+    ) -> anyhow::Result<()>
     where
         BS: Blockstore,
         RT: Runtime<BS>,
     {
         // check that subnet is active
-        if self.status != Status::Active {
+        if !self.accepts_checkpoints(rt.store())? {
             return Err(anyhow!(
                 "submitting checkpoints is not allowed while subnet is not active"
             ));
@@ -308,7 +1091,8 @@ impl State {
         }
 
         // check the source is correct
-        if *ch.source() != SubnetID::new(&self.parent_id, rt.message().receiver()) {
+        let subnet = SubnetID::new(&self.parent_id, rt.message().receiver());
+        if *ch.source() != subnet {
             return Err(anyhow!("submitting checkpoint with the wrong source"));
         }
 
@@ -319,15 +1103,184 @@ impl State {
             ));
         }
 
-        // check signature
-        let caller = rt.message().caller();
-        let pkey = resolve_secp_bls(rt, &caller)?;
+        // check the cross-net messages released by the checkpoint
+        self.verify_cross_msgs(&subnet, ch)?;
 
-        rt.verify_signature(
-            &RawBytes::deserialize(&ch.signature().clone().into())?,
-            &pkey,
-            &ch.cid().to_bytes(),
-        )?;
+        // check the commitments of child subnets
+        verify_children(&subnet, ch)?;
+
+        // check signature against the signing keys of the validator
+        let keys = self
+            .validator_set
+            .iter()
+            .find(|v| v.addr == *validator)
+            .map(|v| v.signing_keys(rt.curr_epoch()))
+            .ok_or_else(|| anyhow!("{} is not a validator", validator))?;
+        self.verify_checkpoint_signature(rt, &keys, ch)?;
+
+        Ok(())
+    }
+
+    /// Verifies that the signature of a checkpoint was produced
+    /// by one of the given keys.
+    pub fn verify_checkpoint_signature<BS, RT>(
+        &self,
+        rt: &mut RT,
+        keys: &[Address],
+        ch: &Checkpoint,
+    ) -> anyhow::Result<()>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        let sig = RawBytes::deserialize(&ch.signature().clone().into())?;
+        let plaintext = ch.cid().to_bytes();
+        for key in keys {
+            if rt.verify_signature(&sig, key, &plaintext).is_ok() {
+                return Ok(());
+            }
+        }
+        Err(anyhow!(
+            "checkpoint not signed by any of the keys of the validator"
+        ))
+    }
+
+    /// Registers a new signing key for a validator that takes effect after
+    /// `SIGNING_KEY_ROTATION_DELAY`. The previous key is still accepted for
+    /// `key_rotation_windows` checkpoint windows after that.
+    pub fn rotate_signing_key<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        addr: &Address,
+        key: &Address,
+        curr_epoch: ChainEpoch,
+    ) -> Result<(), ActorError> {
+        self.check_key_unused(key, addr, curr_epoch)?;
+
+        let effective_epoch = curr_epoch + SIGNING_KEY_ROTATION_DELAY;
+        let retire_epoch =
+            effective_epoch + self.key_rotation_windows as ChainEpoch * self.check_period;
+        let v = self
+            .validator_set
+            .iter_mut()
+            .find(|v| v.addr == *addr)
+            .ok_or_else(|| actor_error!(forbidden, "{} is not a validator", addr))?;
+
+        // a previous rotation that already took effect is applied first
+        v.apply_pending_key(curr_epoch);
+        if v.pending_key.is_some() {
+            return Err(actor_error!(
+                illegal_state,
+                "a rotation of the signing key of the validator is already pending"
+            ));
+        }
+        if v.key_addr == *key {
+            return Err(actor_error!(
+                illegal_argument,
+                "{} is already the signing key of the validator",
+                key
+            ));
+        }
+
+        let rotation = KeyRotation {
+            old_key: v.key_addr,
+            new_key: *key,
+            epoch: effective_epoch,
+        };
+        v.pending_key = Some(PendingKey {
+            key: *key,
+            effective_epoch,
+            retire_epoch,
+        });
+
+        self.key_history
+            .modify(store, |hamt| {
+                let k = BytesKey::from(addr.to_bytes());
+                let mut history = hamt.get(&k)?.cloned().unwrap_or_default();
+                history.push(rotation);
+                hamt.set(k, history)?;
+                Ok(true)
+            })
+            .map_err(|e| {
+                e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "cannot record key rotation")
+            })?;
+
+        Ok(())
+    }
+
+    /// Get the rotations of the signing key of a validator.
+    pub fn get_key_history<BS: Blockstore>(
+        &self,
+        store: &BS,
+        addr: &Address,
+    ) -> anyhow::Result<Vec<KeyRotation>> {
+        let hamt = self.key_history.load(store)?;
+        let history = hamt.get(&BytesKey::from(addr.to_bytes()))?;
+        Ok(history.cloned().unwrap_or_default())
+    }
+
+    /// Checks the bottom-up cross-net messages metadata included in a checkpoint,
+    /// and with the `gateway-extensions` feature, that the value it releases is
+    /// within the circulating supply of the subnet.
+    fn verify_cross_msgs(&self, subnet: &SubnetID, ch: &Checkpoint) -> anyhow::Result<()> {
+        let metas = &ch.data.cross_msgs;
+        if metas.len() > MAX_CROSS_MSG_METAS {
+            return Err(anyhow!(
+                "checkpoint includes {} cross-msg metas, the maximum is {}",
+                metas.len(),
+                MAX_CROSS_MSG_METAS
+            ));
+        }
+
+        let mut seen = Vec::new();
+        for meta in metas {
+            if seen.contains(&(&meta.from, &meta.to)) {
+                return Err(anyhow!(
+                    "duplicate cross-msg meta from {} to {}",
+                    meta.from,
+                    meta.to
+                ));
+            }
+            seen.push((&meta.from, &meta.to));
+
+            if meta.from != *subnet && !is_descendant(&meta.from, subnet) {
+                return Err(anyhow!(
+                    "cross-msgs from {} are not originated in the subnet",
+                    meta.from
+                ));
+            }
+            if meta.to == *subnet || is_descendant(&meta.to, subnet) {
+                return Err(anyhow!(
+                    "cross-msgs to {} are not bottom-up messages",
+                    meta.to
+                ));
+            }
+            if meta.value < TokenAmount::zero() {
+                return Err(anyhow!("cross-msgs to {} have negative value", meta.to));
+            }
+            // metas don't carry the number of messages, but they need
+            // to reference at least one.
+            if meta.msgs_cid == Default::default() {
+                return Err(anyhow!(
+                    "cross-msg meta from {} to {} doesn't include any message",
+                    meta.from,
+                    meta.to
+                ));
+            }
+        }
+
+        // only value funded into the subnet can be released
+        #[cfg(feature = "gateway-extensions")]
+        {
+            let value = cross_msgs_value(ch);
+            if value > self.circ_supply {
+                return Err(anyhow!(
+                    "checkpoint releases {} but the circulating supply of the subnet is {}",
+                    value,
+                    self.circ_supply
+                ));
+            }
+        }
 
         Ok(())
     }
@@ -346,22 +1299,520 @@ impl State {
                 }
             }
         }
-        Ok(Cid::default())
+        // the first checkpoint of the chain points to the genesis.
+        Ok(self.genesis_cid)
     }
 
     pub fn flush_checkpoint<BS: Blockstore>(
         &mut self,
         store: &BS,
         ch: &Checkpoint,
+        signers: &[Address],
+        rewards: Vec<RewardCredit>,
+        curr_epoch: ChainEpoch,
     ) -> anyhow::Result<()> {
-        let epoch = ch.epoch();
+        let key = BytesKey::from(ch.epoch().to_ne_bytes().to_vec());
         self.checkpoints.modify(store, |hamt| {
-            hamt.set(BytesKey::from(epoch.to_ne_bytes().to_vec()), ch.clone())
+            hamt.set(key.clone(), ch.clone())
                 .map_err(|e| anyhow!("failed to set checkpoint: {:?}", e))?;
             Ok(true)
         })?;
+
+        // value released bottom-up leaves the subnet
+        #[cfg(feature = "gateway-extensions")]
+        self.circ_supply -= cross_msgs_value(ch);
+
+        // account for the windows skipped since the last commitment
+        let prev_committed_epoch = self.last_committed_epoch;
+        let mut skipped_windows = 0;
+        if ch.epoch() > self.last_committed_epoch {
+            skipped_windows =
+                ((ch.epoch() - self.last_committed_epoch) / self.check_period - 1) as u64;
+            self.missed_windows += skipped_windows;
+            for v in self.validator_set.iter_mut() {
+                v.missed_windows += skipped_windows;
+            }
+            self.last_committed_epoch = ch.epoch();
+        }
+
+        // record the participation of validators in the commitment
+        let period = self.participation_policy.period as usize;
+        let mut participants = Vec::new();
+        if period > 0 {
+            for v in self.validator_set.iter_mut() {
+                v.participation.push(signers.contains(&v.addr));
+                if v.participation.len() > period {
+                    v.participation.remove(0);
+                }
+                participants.push(v.addr);
+            }
+        }
+
+        // keep track of the commitment while it can still be challenged
+        self.commits.modify(store, |hamt| {
+            hamt.set(
+                key,
+                CommitInfo {
+                    epoch: curr_epoch,
+                    signers: signers.to_vec(),
+                    rewards,
+                    participants,
+                    skipped_windows,
+                    prev_committed_epoch,
+                },
+            )
+            .map_err(|e| anyhow!("failed to set commit info: {:?}", e))?;
+            Ok(true)
+        })?;
+        Ok(())
+    }
+
+    /// Reverts the checkpoint committed for `epoch` along with all the
+    /// checkpoints built on top of it.
+    ///
+    /// The rewards credited for the reverted commitments are returned to the
+    /// reward pool as long as they haven't been claimed yet, and the liveness
+    /// records of validators are rolled back.
+    pub fn revert_checkpoints<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        epoch: ChainEpoch,
+    ) -> anyhow::Result<()> {
+        let mut reverted = Vec::new();
+        self.checkpoints
+            .load(store)?
+            .for_each(|_, ch: &Checkpoint| {
+                if ch.epoch() >= epoch {
+                    reverted.push(ch.clone());
+                }
+                Ok(())
+            })?;
+        // roll back the most recent commitments first
+        reverted.sort_by_key(|ch| std::cmp::Reverse(ch.epoch()));
+
+        for ch in reverted {
+            let key = BytesKey::from(ch.epoch().to_ne_bytes().to_vec());
+            self.checkpoints.modify(store, |hamt| {
+                hamt.delete(&key)?;
+                Ok(true)
+            })?;
+            let mut commit = None;
+            self.commits.modify(store, |hamt| {
+                commit = hamt.delete(&key)?.map(|(_, c)| c);
+                Ok(true)
+            })?;
+
+            // the value released by the checkpoint is back in the subnet
+            #[cfg(feature = "gateway-extensions")]
+            self.circ_supply += cross_msgs_value(&ch);
+
+            if let Some(commit) = commit {
+                self.revert_commit(store, commit)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn revert_commit<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        commit: CommitInfo,
+    ) -> anyhow::Result<()> {
+        // take back the rewards that are still unclaimed
+        let mut recovered = TokenAmount::zero();
+        self.rewards.modify(store, |hamt| {
+            for r in &commit.rewards {
+                let key = BytesKey::from(r.addr.to_bytes());
+                let unclaimed = hamt.get(&key)?.cloned().unwrap_or_else(TokenAmount::zero);
+                let amount = if unclaimed < r.amount {
+                    unclaimed.clone()
+                } else {
+                    r.amount.clone()
+                };
+                if unclaimed == amount {
+                    hamt.delete(&key)?;
+                } else {
+                    hamt.set(key, unclaimed - &amount)?;
+                }
+                recovered += amount;
+            }
+            Ok(true)
+        })?;
+        self.unclaimed_rewards -= &recovered;
+        self.reward_pool += recovered;
+
+        // the windows skipped and the participation recorded
+        // by the commitment are accounted again on recommitment
+        self.missed_windows = self.missed_windows.saturating_sub(commit.skipped_windows);
+        for v in self.validator_set.iter_mut() {
+            if commit.participants.contains(&v.addr) {
+                v.missed_windows = v.missed_windows.saturating_sub(commit.skipped_windows);
+                v.participation.pop();
+            }
+        }
+
+        // reverted windows need to be committed again
+        if self.last_committed_epoch > commit.prev_committed_epoch {
+            self.last_committed_epoch = commit.prev_committed_epoch;
+        }
+        Ok(())
+    }
+
+    /// Slashes all the stake of an address removing it from the validator set.
+    ///
+    /// It returns the amount slashed, including the stake delegated to the address.
+    pub fn slash_stake<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        addr: &Address,
+    ) -> anyhow::Result<TokenAmount> {
+        let stake = self
+            .get_stake(store, addr)?
+            .unwrap_or_else(TokenAmount::zero);
+        self.burn_stake(store, addr, &stake)
+    }
+
+    /// Burns part of the stake of an address. The address is removed from the
+    /// validator set if it is left with less than the minimum stake.
+    ///
+    /// The stake delegated to the address is burnt in the same proportion, and
+    /// the total amount burnt is returned.
+    pub fn burn_stake<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        addr: &Address,
+        amount: &TokenAmount,
+    ) -> anyhow::Result<TokenAmount> {
+        let stake = self
+            .get_stake(store, addr)?
+            .unwrap_or_else(TokenAmount::zero);
+        if stake < *amount {
+            return Err(anyhow!("address not enough stake to burn: {:?}", addr));
+        }
+
+        let remaining = stake - amount;
+        self.stake.modify(store, |hamt| {
+            hamt.set(BytesKey::from(addr.to_bytes()), remaining.clone())?;
+            Ok(true)
+        })?;
+
+        self.total_stake -= amount;
+        if remaining < self.min_validator_stake {
+            self.validator_set.retain(|x| x.addr != *addr);
+        }
+
+        let mut burnt = amount.clone();
+        if stake > TokenAmount::zero() {
+            let mut delegations = self.get_delegations(store, addr)?;
+            for d in delegations.iter_mut() {
+                let cut =
+                    TokenAmount::from_atto(amount.atto().clone() * d.amount.atto() / stake.atto());
+                d.amount -= &cut;
+                self.total_delegated -= &cut;
+                burnt += cut;
+            }
+            delegations.retain(|d| d.amount > TokenAmount::zero());
+            self.set_delegations(store, addr, delegations)?;
+        }
+
+        Ok(burnt)
+    }
+
+    /// Penalizes validators whose participation over the last committed
+    /// windows is below the minimum required by the participation policy.
+    ///
+    /// It returns the total amount of stake burnt, and the stake moved to
+    /// unbonding for validators that left the validator set.
+    pub fn apply_participation_penalties<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        curr_epoch: ChainEpoch,
+    ) -> anyhow::Result<(TokenAmount, TokenAmount)> {
+        let policy = self.participation_policy.clone();
+        let mut burnt = TokenAmount::zero();
+        let mut ejected = TokenAmount::zero();
+        if policy.period == 0 || policy.penalty == LivenessPenalty::None {
+            return Ok((burnt, ejected));
+        }
+
+        let offenders: Vec<Address> = self
+            .validator_set
+            .iter()
+            .filter(|v| {
+                let signed = v.participation.iter().filter(|p| **p).count() as u64;
+                v.participation.len() as u64 == policy.period
+                    && signed * 100 < policy.min_participation * policy.period
+            })
+            .map(|v| v.addr)
+            .collect();
+
+        for addr in offenders {
+            match policy.penalty {
+                LivenessPenalty::Eject => {
+                    ejected += self.eject_validator(store, &addr, curr_epoch)?;
+                }
+                LivenessPenalty::Burn => {
+                    let stake = self
+                        .get_stake(store, &addr)?
+                        .unwrap_or_else(TokenAmount::zero);
+                    let amount = TokenAmount::from_atto(
+                        stake.atto().clone() * policy.burn_percentage / 100u64,
+                    );
+                    burnt += self.burn_stake(store, &addr, &amount)?;
+                    if !self.is_validator(&addr) {
+                        ejected += self.undelegate_all(store, &addr, curr_epoch)?;
+                    }
+
+                    // start a new period for the validator
+                    if let Some(v) = self.validator_set.iter_mut().find(|x| x.addr == addr) {
+                        v.participation.clear();
+                    }
+                }
+                LivenessPenalty::None => {}
+            }
+        }
+
+        Ok((burnt, ejected))
+    }
+
+    /// Updates the commission rate of a validator.
+    ///
+    /// Decreases are applied straight away, while increases are limited to
+    /// `MAX_COMMISSION_INCREASE` points every `COMMISSION_UPDATE_PERIOD`.
+    pub fn set_commission(
+        &mut self,
+        addr: &Address,
+        commission: u64,
+        curr_epoch: ChainEpoch,
+    ) -> Result<(), ActorError> {
+        if commission > 100 {
+            return Err(actor_error!(
+                illegal_argument,
+                "commission rate can't be greater than 100"
+            ));
+        }
+        let v = self
+            .validator_set
+            .iter_mut()
+            .find(|v| v.addr == *addr)
+            .ok_or_else(|| actor_error!(forbidden, "{} is not a validator", addr))?;
+
+        if commission > v.commission {
+            if commission - v.commission > MAX_COMMISSION_INCREASE {
+                return Err(actor_error!(
+                    illegal_argument,
+                    "commission rate can only be increased by {} points",
+                    MAX_COMMISSION_INCREASE
+                ));
+            }
+            if let Some(updated_at) = v
+                .commission_updated_at
+                .filter(|e| curr_epoch < e + COMMISSION_UPDATE_PERIOD)
+            {
+                return Err(actor_error!(
+                    illegal_state,
+                    "commission rate was increased at epoch {}, less than {} epochs ago",
+                    updated_at,
+                    COMMISSION_UPDATE_PERIOD
+                ));
+            }
+            v.commission_updated_at = Some(curr_epoch);
+        }
+        v.commission = commission;
+
         Ok(())
     }
+
+    /// Get the unclaimed rewards of an address.
+    pub fn get_rewards<BS: Blockstore>(
+        &self,
+        store: &BS,
+        addr: &Address,
+    ) -> anyhow::Result<Option<TokenAmount>> {
+        let hamt = self.rewards.load(store)?;
+        let amount = hamt.get(&BytesKey::from(addr.to_bytes()))?;
+        Ok(amount.cloned())
+    }
+
+    /// Distributes the checkpoint reward among the signers of a committed
+    /// checkpoint proportionally to their voting power. Each signer keeps its
+    /// commission and splits the rest of its share with its delegators
+    /// proportionally to their stake.
+    ///
+    /// The reward is capped by the funds left in the reward pool, and the
+    /// remainder of the integer division is kept in the pool.
+    pub fn distribute_rewards<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        signers: &[Address],
+    ) -> anyhow::Result<Vec<RewardCredit>> {
+        let reward = if self.checkpoint_reward > self.reward_pool {
+            self.reward_pool.clone()
+        } else {
+            self.checkpoint_reward.clone()
+        };
+        if reward == TokenAmount::zero() {
+            return Ok(Vec::new());
+        }
+
+        let mut powers = Vec::new();
+        let mut total = TokenAmount::zero();
+        for addr in signers {
+            let stake = self
+                .get_stake(store, addr)?
+                .unwrap_or_else(TokenAmount::zero);
+            let delegations = self.get_delegations(store, addr)?;
+            let power = delegations
+                .iter()
+                .fold(stake.clone(), |acc, d| acc + &d.amount);
+            let commission = self
+                .validator_set
+                .iter()
+                .find(|v| v.addr == *addr)
+                .map(|v| v.commission)
+                .unwrap_or(0);
+            total += &power;
+            powers.push((addr, stake, delegations, power, commission));
+        }
+        if total == TokenAmount::zero() {
+            return Ok(Vec::new());
+        }
+
+        let mut distributed = TokenAmount::zero();
+        let mut rewards = Vec::new();
+        self.rewards.modify(store, |hamt| {
+            for (addr, stake, delegations, power, commission) in &powers {
+                if *power == TokenAmount::zero() {
+                    continue;
+                }
+                let share = reward.atto().clone() * power.atto() / total.atto();
+                let fee = share.clone() * *commission / 100u64;
+                let rest = share - &fee;
+
+                // the validator takes its commission and splits
+                // the rest with its delegators
+                let mut credits = vec![(**addr, TokenAmount::from_atto(fee))];
+                credits.extend(
+                    std::iter::once((**addr, stake))
+                        .chain(delegations.iter().map(|d| (d.delegator, &d.amount)))
+                        .map(|(to, amount)| {
+                            let credit = rest.clone() * amount.atto() / power.atto();
+                            (to, TokenAmount::from_atto(credit))
+                        }),
+                );
+                for (to, credit) in credits {
+                    if credit == TokenAmount::zero() {
+                        continue;
+                    }
+                    let key = BytesKey::from(to.to_bytes());
+                    let prev = hamt.get(&key)?.unwrap_or(&TokenAmount::zero()).clone();
+                    hamt.set(key, prev + &credit)?;
+                    distributed += &credit;
+                    rewards.push(RewardCredit {
+                        addr: to,
+                        amount: credit,
+                    });
+                }
+            }
+            Ok(true)
+        })?;
+
+        self.reward_pool -= &distributed;
+        self.unclaimed_rewards += distributed;
+
+        Ok(rewards)
+    }
+
+    /// Removes the unclaimed rewards of an address returning the amount claimed.
+    pub fn claim_rewards<BS: Blockstore>(
+        &mut self,
+        store: &BS,
+        addr: &Address,
+    ) -> anyhow::Result<TokenAmount> {
+        let mut amount = TokenAmount::zero();
+        self.rewards.modify(store, |hamt| {
+            if let Some((_, r)) = hamt.delete(&BytesKey::from(addr.to_bytes()))? {
+                amount = r;
+            }
+            Ok(true)
+        })?;
+
+        self.unclaimed_rewards -= &amount;
+        Ok(amount)
+    }
+
+    /// Balance of the actor held for checkpoint rewards.
+    pub fn reward_funds(&self) -> TokenAmount {
+        self.reward_pool.clone() + &self.unclaimed_rewards
+    }
+}
+
+/// Checks that the child checkpoints aggregated in a checkpoint are well-formed
+/// and belong to direct children of the subnet.
+fn verify_children(subnet: &SubnetID, ch: &Checkpoint) -> Result<(), ActorError> {
+    let mut seen = Vec::new();
+    for child in &ch.data.children {
+        if child.source.parent().as_ref() != Some(subnet) {
+            return Err(actor_error!(
+                illegal_argument,
+                "child checkpoint source {} is not a child of {}",
+                child.source,
+                subnet
+            ));
+        }
+        if seen.contains(&&child.source) {
+            return Err(actor_error!(
+                illegal_argument,
+                "duplicate checkpoints for child {}",
+                child.source
+            ));
+        }
+        seen.push(&child.source);
+
+        if child.checks.is_empty() {
+            return Err(actor_error!(
+                illegal_argument,
+                "no checkpoints committed for child {}",
+                child.source
+            ));
+        }
+        let mut cids = Vec::new();
+        for check in &child.checks {
+            let cid = check.cid();
+            if cids.contains(&cid) {
+                return Err(actor_error!(
+                    illegal_argument,
+                    "duplicate checkpoint {} for child {}",
+                    cid,
+                    child.source
+                ));
+            }
+            cids.push(cid);
+        }
+    }
+    Ok(())
+}
+
+/// Total value released by the cross-net messages of a checkpoint.
+#[cfg(feature = "gateway-extensions")]
+fn cross_msgs_value(ch: &Checkpoint) -> TokenAmount {
+    ch.data
+        .cross_msgs
+        .iter()
+        .fold(TokenAmount::zero(), |acc, meta| acc + &meta.value)
+}
+
+/// Returns true if `id` is nested under `ancestor`.
+fn is_descendant(id: &SubnetID, ancestor: &SubnetID) -> bool {
+    let mut curr = id.parent();
+    while let Some(p) = curr {
+        if p == *ancestor {
+            return true;
+        }
+        curr = p.parent();
+    }
+    false
 }
 
 impl Default for State {
@@ -373,12 +1824,49 @@ impl Default for State {
             consensus: ConsensusType::Delegated,
             min_validator_stake: TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
             total_stake: TokenAmount::zero(),
+            circ_supply: TokenAmount::zero(),
             finality_threshold: 5,
             check_period: 10,
             genesis: Vec::new(),
+            genesis_cid: Cid::default(),
             status: Status::Instantiated,
+            inactive_since: None,
+            unregistered: false,
             checkpoints: TCid::default(),
+            commits: TCid::default(),
+            challenge_window: 0,
+            last_committed_epoch: 0,
+            missed_windows: 0,
+            max_missed_windows: 0,
+            participation_policy: ParticipationPolicy::default(),
+            reward_pool: TokenAmount::zero(),
+            checkpoint_reward: TokenAmount::zero(),
+            unclaimed_rewards: TokenAmount::zero(),
+            rewards: TCid::default(),
+            leaving_penalty: LeavingPenalty::default(),
+            allowed_validator_codes: Vec::new(),
+            key_rotation_windows: 0,
+            key_history: TCid::default(),
+            permissioned: false,
+            owner: None,
+            allow_list: Vec::new(),
+            allow_list_votes: TCid::default(),
+            removal_votes: TCid::default(),
+            outbox: Vec::new(),
+            outbox_stalled_since: None,
+            dropped_messages: 0,
+            gateway_collateral: TokenAmount::zero(),
+            unforwarded_stake: TokenAmount::zero(),
+            fee_config: FeeConfig::default(),
+            collected_fees: TokenAmount::zero(),
+            treasury: TokenAmount::zero(),
+            treasury_beneficiary: None,
+            spend_votes: TCid::default(),
             stake: TCid::default(),
+            delegations: TCid::default(),
+            total_delegated: TokenAmount::zero(),
+            unbonding: TCid::default(),
+            rejoin_epochs: TCid::default(),
             window_checks: TCid::default(),
             validator_set: Vec::new(),
             min_validators: 0,
@@ -1,24 +1,140 @@
+use anyhow::anyhow;
+use cid::Cid;
 use fvm_ipld_encoding::repr::*;
 use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
 use fvm_ipld_encoding::{Cbor, RawBytes};
 use fvm_shared::address::Address;
 use fvm_shared::clock::ChainEpoch;
+use fvm_shared::crypto::signature::Signature;
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::MethodNum;
-use ipc_gateway::SubnetID;
-
-/// Optional leaving coefficient to penalize
-/// validators leaving the subnet.
-// It should be a float between 0-1 but
-// setting it to 1_u64 for now for convenience.
-// This will change once we figure out the econ model.
-pub const LEAVING_COEFF: u64 = 1;
+use ipc_gateway::{Checkpoint, SubnetID};
+use num_traits::Zero;
+use std::collections::HashSet;
+
 pub const TESTING_ID: u64 = 339;
+/// Number of epochs a subnet is allowed to remain `Inactive`
+/// before it is automatically moved to `Terminating`.
+pub const INACTIVE_GRACE_PERIOD: ChainEpoch = 2880;
+/// Maximum number of cross-net message metas a checkpoint
+/// is allowed to carry.
+pub const MAX_CROSS_MSG_METAS: usize = 256;
+/// Number of epochs undelegated stake remains locked
+/// before it can be withdrawn.
+pub const UNBONDING_PERIOD: ChainEpoch = 2880;
+/// Maximum increase of the commission rate of a validator,
+/// in percentage points, per `COMMISSION_UPDATE_PERIOD`.
+pub const MAX_COMMISSION_INCREASE: u64 = 5;
+/// Minimum number of epochs between increases of the
+/// commission rate of a validator.
+pub const COMMISSION_UPDATE_PERIOD: ChainEpoch = 2880;
+/// Number of epochs before a new signing key of a
+/// validator takes effect.
+pub const SIGNING_KEY_ROTATION_DELAY: ChainEpoch = 900;
+/// Number of epochs a message can keep failing at the head
+/// of the outbox before it is dropped.
+pub const OUTBOX_RETRY_PERIOD: ChainEpoch = 2880;
+/// Number of epochs a validator ejected from the validator
+/// set has to wait before joining the subnet again.
+pub const REJOIN_COOLDOWN: ChainEpoch = 2880;
 
 #[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
 pub struct Validator {
     pub addr: Address,
     pub net_addr: String,
+    /// Key checkpoint signatures of the validator are verified with.
+    /// It's the signing key registered by the validator or, if none,
+    /// the public key resolved when it joined.
+    pub key_addr: Address,
+    /// Signing key waiting for the rotation delay to take effect.
+    pub pending_key: Option<PendingKey>,
+    /// Previous signing key, still accepted during the
+    /// overlap window of the last rotation.
+    pub retiring_key: Option<RetiringKey>,
+    /// Checkpoint windows missed by the subnet while
+    /// the validator was part of the validator set.
+    pub missed_windows: u64,
+    /// Whether the validator signed each of the latest committed
+    /// windows, from oldest to newest.
+    pub participation: Vec<bool>,
+    /// Percentage of the checkpoint rewards of the validator
+    /// kept before sharing them with its delegators.
+    pub commission: u64,
+    /// Epoch of the last increase of the commission rate, if any.
+    pub commission_updated_at: Option<ChainEpoch>,
+}
+
+impl Validator {
+    /// Returns the keys accepted for checkpoint signatures at `curr_epoch`.
+    pub fn signing_keys(&self, curr_epoch: ChainEpoch) -> Vec<Address> {
+        let (key, retiring) = match &self.pending_key {
+            Some(p) if curr_epoch >= p.effective_epoch => {
+                (p.key, Some((self.key_addr, p.retire_epoch)))
+            }
+            _ => (
+                self.key_addr,
+                self.retiring_key.as_ref().map(|r| (r.key, r.retire_epoch)),
+            ),
+        };
+
+        let mut keys = vec![key];
+        if let Some((key, retire_epoch)) = retiring {
+            if curr_epoch < retire_epoch {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+
+    /// Returns whether `key` is the current, pending or still
+    /// accepted retiring signing key of the validator.
+    pub fn holds_key(&self, key: &Address, curr_epoch: ChainEpoch) -> bool {
+        self.key_addr == *key
+            || self.pending_key.as_ref().map_or(false, |p| p.key == *key)
+            || self
+                .retiring_key
+                .as_ref()
+                .map_or(false, |r| r.key == *key && curr_epoch < r.retire_epoch)
+    }
+
+    /// Replaces the signing key with the pending one if its rotation
+    /// delay is over, retiring the previous key.
+    pub fn apply_pending_key(&mut self, curr_epoch: ChainEpoch) {
+        match &self.pending_key {
+            Some(p) if curr_epoch >= p.effective_epoch => {
+                self.retiring_key = Some(RetiringKey {
+                    key: self.key_addr,
+                    retire_epoch: p.retire_epoch,
+                });
+                self.key_addr = p.key;
+                self.pending_key = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct PendingKey {
+    pub key: Address,
+    pub effective_epoch: ChainEpoch,
+    /// Epoch from which the key it replaces is no longer accepted.
+    pub retire_epoch: ChainEpoch,
+}
+
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct RetiringKey {
+    pub key: Address,
+    pub retire_epoch: ChainEpoch,
+}
+
+/// Record of the rotation of the signing key of a validator.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct KeyRotation {
+    pub old_key: Address,
+    pub new_key: Address,
+    /// Epoch the new key takes effect.
+    pub epoch: ChainEpoch,
 }
 
 #[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
@@ -28,6 +144,61 @@ pub struct Votes {
 
 impl Cbor for Votes {}
 
+/// Information about the commitment of a checkpoint.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct CommitInfo {
+    /// Epoch in which the checkpoint was committed.
+    pub epoch: ChainEpoch,
+    /// Validators whose votes committed the checkpoint.
+    pub signers: Vec<Address>,
+    /// Rewards credited for the commitment.
+    pub rewards: Vec<RewardCredit>,
+    /// Validators whose participation was recorded for the commitment.
+    pub participants: Vec<Address>,
+    /// Windows accounted as missed by the commitment.
+    pub skipped_windows: u64,
+    /// Last committed epoch before the commitment.
+    pub prev_committed_epoch: ChainEpoch,
+}
+
+/// Reward credited to an address for the commitment of a checkpoint.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct RewardCredit {
+    pub addr: Address,
+    pub amount: TokenAmount,
+}
+
+/// Liveness statistics of a validator.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct ValidatorLiveness {
+    pub addr: Address,
+    pub missed_windows: u64,
+}
+
+/// Liveness statistics of the subnet returned by the `LivenessStats` query.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct LivenessStats {
+    pub last_committed_epoch: ChainEpoch,
+    pub missed_windows: u64,
+    pub consecutive_missed_windows: u64,
+    pub validators: Vec<ValidatorLiveness>,
+}
+impl Cbor for LivenessStats {}
+
+/// Proof that a validator signed two conflicting
+/// checkpoints for the same window.
+///
+/// Checkpoints are only committed with the votes of validators, so
+/// equivocation is the only fraud a committed checkpoint can be
+/// challenged with.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct FraudProof {
+    pub signer: Address,
+    pub first: Checkpoint,
+    pub second: Checkpoint,
+}
+impl Cbor for FraudProof {}
+
 /// Consensus types supported by hierarchical consensus
 #[derive(PartialEq, Eq, Clone, Copy, Debug, Deserialize_repr, Serialize_repr)]
 #[repr(u64)]
@@ -62,18 +233,332 @@ pub struct ConstructParams {
     pub check_period: ChainEpoch,
     // genesis is no longer generated by the actor
     // on-the-fly, but it is accepted as a construct
-    // param
+    // param. It is expected to be the CBOR
+    // serialization of a `Genesis`.
     pub genesis: Vec<u8>,
+    /// Validators seeded at construction. They need to be
+    /// included in the genesis and their stake is funded by
+    /// the value sent to the constructor.
+    pub initial_validators: Vec<GenesisValidator>,
+    /// Number of epochs after the commitment of a checkpoint
+    /// during which fraud proofs against it are accepted.
+    pub challenge_window: ChainEpoch,
+    /// Number of consecutive checkpoint windows that can be
+    /// missed before the subnet is moved to `Inactive`. Zero
+    /// disables the check.
+    pub max_missed_windows: u64,
+    pub participation_policy: ParticipationPolicy,
+    /// Reward distributed from the reward pool among the
+    /// validators that signed each committed checkpoint.
+    pub checkpoint_reward: TokenAmount,
+    pub leaving_penalty: LeavingPenalty,
+    /// Code CIDs of non-account actors allowed to become
+    /// validators, e.g. multisig or EVM actors.
+    pub allowed_validator_codes: Vec<Cid>,
+    /// Number of checkpoint windows during which the previous
+    /// signing key of a validator is still accepted after a rotation.
+    pub key_rotation_windows: u64,
+    /// Restricts the validators of the subnet to an allow-list.
+    /// The subnet is permissionless if `None`.
+    pub permissioning: Option<Permissioning>,
+    pub fee_config: FeeConfig,
+    /// Address the treasury and the undistributed reward pool of the
+    /// subnet are returned to when the subnet is killed. They are
+    /// burnt if `None`.
+    pub treasury_beneficiary: Option<Address>,
 }
 impl Cbor for ConstructParams {}
 
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct GenesisValidator {
+    pub addr: Address,
+    pub net_addr: String,
+    pub stake: TokenAmount,
+}
+
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct GenesisBalance {
+    pub addr: Address,
+    pub amount: TokenAmount,
+}
+
+/// Genesis of the subnet committed in the actor at construction.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct Genesis {
+    pub validators: Vec<GenesisValidator>,
+    pub balances: Vec<GenesisBalance>,
+    pub consensus: ConsensusType,
+    pub check_period: ChainEpoch,
+    pub timestamp: u64,
+}
+impl Cbor for Genesis {}
+
+impl Genesis {
+    /// Checks that the genesis is well-formed and consistent with the
+    /// consensus configuration of the subnet.
+    pub fn validate(
+        &self,
+        consensus: ConsensusType,
+        check_period: ChainEpoch,
+    ) -> anyhow::Result<()> {
+        if self.consensus != consensus {
+            return Err(anyhow!(
+                "genesis consensus doesn't match the subnet consensus"
+            ));
+        }
+        if self.check_period != check_period {
+            return Err(anyhow!(
+                "genesis checkpoint period doesn't match the subnet one"
+            ));
+        }
+
+        let mut seen = HashSet::new();
+        for v in &self.validators {
+            if !seen.insert(v.addr) {
+                return Err(anyhow!("duplicate genesis validator: {}", v.addr));
+            }
+            if v.net_addr.is_empty() {
+                return Err(anyhow!("genesis validator {} has no net address", v.addr));
+            }
+            if v.stake <= TokenAmount::zero() {
+                return Err(anyhow!("genesis validator {} has no stake", v.addr));
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for b in &self.balances {
+            if !seen.insert(b.addr) {
+                return Err(anyhow!("duplicate genesis balance: {}", b.addr));
+            }
+            if b.amount < TokenAmount::zero() {
+                return Err(anyhow!("genesis balance of {} is negative", b.addr));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Penalty for validators that don't participate enough in checkpointing.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Deserialize_repr, Serialize_repr)]
+#[repr(u64)]
+pub enum LivenessPenalty {
+    None,
+    Burn,
+    Eject,
+}
+
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct ParticipationPolicy {
+    /// Number of committed windows over which participation is computed.
+    pub period: u64,
+    /// Minimum percentage of committed windows a validator needs to sign.
+    pub min_participation: u64,
+    pub penalty: LivenessPenalty,
+    /// Percentage of the stake burnt by `LivenessPenalty::Burn`.
+    pub burn_percentage: u64,
+}
+
+impl ParticipationPolicy {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.min_participation > 100 {
+            return Err(anyhow!("minimum participation is greater than 100%"));
+        }
+        if self.burn_percentage > 100 {
+            return Err(anyhow!("burn percentage is greater than 100%"));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ParticipationPolicy {
+    fn default() -> Self {
+        Self {
+            period: 0,
+            min_participation: 0,
+            penalty: LivenessPenalty::None,
+            burn_percentage: 0,
+        }
+    }
+}
+
+/// Destination of the stake withheld from validators leaving the subnet.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Deserialize_repr, Serialize_repr)]
+#[repr(u64)]
+pub enum PenaltyDestination {
+    Burn,
+    /// Shared among the remaining validators proportionally to their stake.
+    Redistribute,
+}
+
+/// Fraction of their stake withheld from validators leaving the subnet.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct LeavingPenalty {
+    pub numerator: u64,
+    pub denominator: u64,
+    pub destination: PenaltyDestination,
+}
+
+impl LeavingPenalty {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.denominator == 0 {
+            return Err(anyhow!("leaving penalty denominator is zero"));
+        }
+        if self.numerator > self.denominator {
+            return Err(anyhow!("leaving penalty is greater than one"));
+        }
+        Ok(())
+    }
+
+    /// Returns the penalty charged on `stake`, rounded down.
+    pub fn apply(&self, stake: &TokenAmount) -> TokenAmount {
+        TokenAmount::from_atto(stake.atto().clone() * self.numerator / self.denominator)
+    }
+}
+
+impl Default for LeavingPenalty {
+    fn default() -> Self {
+        Self {
+            numerator: 0,
+            denominator: 1,
+            destination: PenaltyDestination::Burn,
+        }
+    }
+}
+
+/// Destination of the fees collected by the subnet.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Deserialize_repr, Serialize_repr)]
+#[repr(u64)]
+pub enum FeeDestination {
+    RewardPool,
+    Treasury,
+}
+
+/// Fees charged by the subnet on checkpoint commitment.
+///
+/// Fees are collected by the gateway with a method the upstream gateway
+/// doesn't provide yet, so they can only be charged when built with the
+/// `gateway-extensions` feature.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct FeeConfig {
+    /// Fee charged for the commitment of a checkpoint.
+    pub checkpoint_fee: TokenAmount,
+    /// Fee charged for each cross-net message meta included in a checkpoint.
+    pub cross_msg_fee: TokenAmount,
+    pub destination: FeeDestination,
+}
+
+impl FeeConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.checkpoint_fee < TokenAmount::zero() || self.cross_msg_fee < TokenAmount::zero() {
+            return Err(anyhow!("fees can't be negative"));
+        }
+        #[cfg(not(feature = "gateway-extensions"))]
+        if self.checkpoint_fee > TokenAmount::zero() || self.cross_msg_fee > TokenAmount::zero() {
+            return Err(anyhow!("the gateway can't collect fees for the subnet"));
+        }
+        Ok(())
+    }
+
+    /// Returns the fee charged for the commitment of `ch`.
+    pub fn checkpoint_fee(&self, ch: &Checkpoint) -> TokenAmount {
+        self.checkpoint_fee.clone() + self.cross_msg_fee.clone() * ch.data.cross_msgs.len() as u64
+    }
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        Self {
+            checkpoint_fee: TokenAmount::zero(),
+            cross_msg_fee: TokenAmount::zero(),
+            destination: FeeDestination::RewardPool,
+        }
+    }
+}
+
+/// Stake delegated to a validator.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct Delegation {
+    pub delegator: Address,
+    pub amount: TokenAmount,
+}
+
+/// Undelegated stake waiting for the end of the unbonding period.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct Unbonding {
+    pub amount: TokenAmount,
+    pub release_epoch: ChainEpoch,
+}
+
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct DelegateParams {
+    pub validator: Address,
+    pub amount: TokenAmount,
+}
+impl Cbor for DelegateParams {}
+
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct UndelegateParams {
+    pub validator: Address,
+    pub amount: TokenAmount,
+}
+impl Cbor for UndelegateParams {}
+
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct SetCommissionParams {
+    /// New commission rate as a percentage.
+    pub commission: u64,
+}
+impl Cbor for SetCommissionParams {}
+
 #[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
 pub struct JoinParams {
     pub validator_net_addr: String,
+    /// Secp256k1 or BLS key used to sign checkpoints instead of
+    /// the key of the caller.
+    pub signing_key: Option<SigningKeyParams>,
 }
 impl Cbor for JoinParams {}
 
-pub(crate) struct CrossActorPayload {
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct SigningKeyParams {
+    pub key: Address,
+    /// Signature of the address of the validator by the key,
+    /// proving that the validator holds it.
+    pub proof: Signature,
+}
+impl Cbor for SigningKeyParams {}
+
+/// Proposal to spend funds of the treasury of the subnet.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct SpendParams {
+    pub to: Address,
+    pub amount: TokenAmount,
+}
+impl Cbor for SpendParams {}
+
+/// Configuration of permissioned subnets.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct Permissioning {
+    /// Address allowed to update the allow-list without a vote
+    /// of the validators.
+    pub owner: Option<Address>,
+    /// Initial addresses allowed to join the subnet as validators.
+    pub allow_list: Vec<Address>,
+}
+
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct AllowListParams {
+    pub addr: Address,
+    /// Whether the address is added to or removed from the allow-list.
+    pub allowed: bool,
+}
+impl Cbor for AllowListParams {}
+
+/// Message to another actor, e.g. a message to the gateway
+/// waiting in the outbox of the subnet to be delivered.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct CrossActorPayload {
     pub to: Address,
     pub method: MethodNum,
     pub params: RawBytes,
@@ -90,3 +575,18 @@ impl CrossActorPayload {
         }
     }
 }
+
+/// Collateral of the subnet as seen locally and as recorded by the gateway
+/// according to the messages delivered to it.
+#[derive(Clone, Debug, Serialize_tuple, Deserialize_tuple, PartialEq, Eq)]
+pub struct CollateralStatus {
+    pub local: TokenAmount,
+    pub gateway: TokenAmount,
+    /// Number of messages waiting in the outbox.
+    pub pending_messages: u64,
+    /// Number of messages dropped after failing for too long.
+    pub dropped_messages: u64,
+    /// Whether the gateway and the subnet agree on the collateral.
+    /// It's only checked for registered subnets with an empty outbox.
+    pub consistent: bool,
+}
@@ -2,19 +2,34 @@
 mod test {
     use cid::Cid;
     use fil_actors_runtime::runtime::Runtime;
-    use fil_actors_runtime::test_utils::{expect_abort, ExpectedVerifySig, MockRuntime};
-    use fil_actors_runtime::{cbor, ActorError, INIT_ACTOR_ADDR};
-    use fvm_ipld_encoding::RawBytes;
+    use fil_actors_runtime::test_utils::{
+        expect_abort, ExpectedVerifySig, MockRuntime, ACCOUNT_ACTOR_CODE_ID, MULTISIG_ACTOR_CODE_ID,
+    };
+    use fil_actors_runtime::{cbor, ActorError, BURNT_FUNDS_ACTOR_ADDR, INIT_ACTOR_ADDR};
+    use fvm_ipld_encoding::{Cbor, RawBytes};
     use fvm_shared::address::Address;
+    use fvm_shared::clock::ChainEpoch;
     use fvm_shared::crypto::signature::Signature;
     use fvm_shared::econ::TokenAmount;
     use fvm_shared::error::ExitCode;
-    use ipc_gateway::{Checkpoint, FundParams, SubnetID, MIN_COLLATERAL_AMOUNT};
+    use fvm_shared::METHOD_SEND;
+    #[cfg(feature = "gateway-extensions")]
+    use ipc_gateway::FundParams;
+    use ipc_gateway::{
+        Checkpoint, ChildCheck, CrossMsgMeta, SubnetID, DEFAULT_CHECKPOINT_PERIOD,
+        MIN_COLLATERAL_AMOUNT,
+    };
     use ipc_subnet_actor::{
-        ext, Actor, ConsensusType, ConstructParams, JoinParams, Method, State, Status,
+        ext, Actor, AllowListParams, CollateralStatus, ConsensusType, ConstructParams,
+        CrossActorPayload, DelegateParams, FeeConfig, FeeDestination, FraudProof, Genesis,
+        GenesisBalance, GenesisValidator, JoinParams, KeyRotation, LeavingPenalty, LivenessPenalty,
+        LivenessStats, Method, ParticipationPolicy, PenaltyDestination, Permissioning,
+        SetCommissionParams, SigningKeyParams, SpendParams, State, Status, UndelegateParams,
+        COMMISSION_UPDATE_PERIOD, INACTIVE_GRACE_PERIOD, OUTBOX_RETRY_PERIOD, REJOIN_COOLDOWN,
+        UNBONDING_PERIOD,
     };
     use num_traits::Zero;
-    use primitives::TCid;
+    use primitives::{TCid, TLink};
     use std::str::FromStr;
 
     // just a test address
@@ -31,16 +46,39 @@ mod test {
             min_validators: 0,
             finality_threshold: 0,
             check_period: 0,
-            genesis: vec![],
+            genesis: std_genesis().marshal_cbor().unwrap(),
+            initial_validators: vec![],
+            challenge_window: 100,
+            max_missed_windows: 0,
+            participation_policy: ParticipationPolicy::default(),
+            checkpoint_reward: TokenAmount::zero(),
+            leaving_penalty: LeavingPenalty::default(),
+            allowed_validator_codes: vec![],
+            key_rotation_windows: 1,
+            permissioning: None,
+            fee_config: FeeConfig::default(),
+            treasury_beneficiary: None,
+        }
+    }
+
+    fn std_genesis() -> Genesis {
+        Genesis {
+            validators: vec![],
+            balances: vec![],
+            consensus: ConsensusType::Dummy,
+            check_period: DEFAULT_CHECKPOINT_PERIOD,
+            timestamp: 0,
         }
     }
 
     fn construct_runtime_with_receiver(receiver: Address) -> MockRuntime {
+        construct_runtime_with_params(receiver, std_construct_param())
+    }
+
+    fn construct_runtime_with_params(receiver: Address, params: ConstructParams) -> MockRuntime {
         let caller = *INIT_ACTOR_ADDR;
         let mut runtime = MockRuntime::new(receiver, caller);
 
-        let params = std_construct_param();
-
         runtime.expect_validate_caller_addr(vec![caller]);
 
         runtime
@@ -68,6 +106,156 @@ mod test {
         assert_eq!(state.ipc_gateway_addr, Address::new_id(IPC_GATEWAY_ADDR));
         assert_eq!(state.total_stake, TokenAmount::zero());
         assert_eq!(state.validator_set.is_empty(), true);
+        assert_eq!(state.genesis_cid, std_genesis().cid().unwrap());
+    }
+
+    #[test]
+    fn test_constructor_invalid_genesis() {
+        let mut genesis = std_genesis();
+        genesis.consensus = ConsensusType::Mir;
+        let mut invalid = vec![vec![1, 2, 3], genesis.marshal_cbor().unwrap()];
+
+        let mut genesis = std_genesis();
+        let validator = GenesisValidator {
+            addr: Address::new_id(10),
+            net_addr: String::from("/ip4/127.0.0.1/tcp/1347"),
+            stake: TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
+        };
+        genesis.validators = vec![validator.clone(), validator];
+        invalid.push(genesis.marshal_cbor().unwrap());
+
+        let mut genesis = std_genesis();
+        genesis.balances = vec![GenesisBalance {
+            addr: Address::new_id(10),
+            amount: TokenAmount::from_atto(-1),
+        }];
+        invalid.push(genesis.marshal_cbor().unwrap());
+
+        for genesis in invalid {
+            let mut runtime = MockRuntime::new(Address::new_id(1), *INIT_ACTOR_ADDR);
+            let mut params = std_construct_param();
+            params.genesis = genesis;
+            runtime.expect_validate_caller_addr(vec![*INIT_ACTOR_ADDR]);
+            expect_abort(
+                ExitCode::USR_ILLEGAL_ARGUMENT,
+                runtime.call::<Actor>(
+                    Method::Constructor as u64,
+                    &cbor::serialize(&params, "test").unwrap(),
+                ),
+            );
+        }
+    }
+
+    #[test]
+    fn test_constructor_initial_validators() {
+        let validators = vec![
+            GenesisValidator {
+                addr: Address::new_id(10),
+                net_addr: String::from("/ip4/127.0.0.1/tcp/1347"),
+                stake: TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
+            },
+            GenesisValidator {
+                addr: Address::new_id(20),
+                net_addr: String::from("/ip4/127.0.0.1/tcp/1348"),
+                stake: TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
+            },
+        ];
+        let mut genesis = std_genesis();
+        genesis.validators = validators.clone();
+        let total = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT * 2);
+
+        let mut params = std_construct_param();
+        params.genesis = genesis.marshal_cbor().unwrap();
+        params.initial_validators = validators.clone();
+
+        // the value sent needs to fund the initial validators
+        let mut runtime = MockRuntime::new(Address::new_id(1), *INIT_ACTOR_ADDR);
+        runtime.set_value(TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT));
+        runtime.expect_validate_caller_addr(vec![*INIT_ACTOR_ADDR]);
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
+                Method::Constructor as u64,
+                &cbor::serialize(&params, "test").unwrap(),
+            ),
+        );
+
+        // initial validators need to be part of the genesis
+        let mut other = params.clone();
+        other.initial_validators[1].addr = Address::new_id(30);
+        let mut runtime = MockRuntime::new(Address::new_id(1), *INIT_ACTOR_ADDR);
+        runtime.set_value(total.clone());
+        runtime.expect_validate_caller_addr(vec![*INIT_ACTOR_ADDR]);
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
+                Method::Constructor as u64,
+                &cbor::serialize(&other, "test").unwrap(),
+            ),
+        );
+
+        // initial validators need to be accounts, even if other
+        // actor types are allowed to join
+        let mut other = params.clone();
+        other.allowed_validator_codes = vec![*MULTISIG_ACTOR_CODE_ID];
+        let mut runtime = MockRuntime::new(Address::new_id(1), *INIT_ACTOR_ADDR);
+        runtime.set_value(total.clone());
+        runtime.expect_validate_caller_addr(vec![*INIT_ACTOR_ADDR]);
+        runtime.set_address_actor_type(validators[0].addr, *ACCOUNT_ACTOR_CODE_ID);
+        runtime.set_address_actor_type(validators[1].addr, *MULTISIG_ACTOR_CODE_ID);
+        expect_resolve_key(&mut runtime, validators[0].addr);
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
+                Method::Constructor as u64,
+                &cbor::serialize(&other, "test").unwrap(),
+            ),
+        );
+
+        // the subnet is created active and registered in one message
+        let mut runtime = MockRuntime::new(Address::new_id(1), *INIT_ACTOR_ADDR);
+        runtime.set_value(total.clone());
+        runtime.set_balance(total.clone());
+        runtime.expect_validate_caller_addr(vec![*INIT_ACTOR_ADDR]);
+        for v in &validators {
+            runtime.set_address_actor_type(v.addr, *ACCOUNT_ACTOR_CODE_ID);
+            expect_resolve_key(&mut runtime, v.addr);
+        }
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::register(gateway_addr(), total.clone()),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(
+                Method::Constructor as u64,
+                &cbor::serialize(&params, "test").unwrap(),
+            )
+            .unwrap();
+        runtime.verify();
+
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Active);
+        assert_eq!(st.total_stake, total);
+        assert_eq!(st.validator_set.len(), 2);
+        for v in validators {
+            assert_eq!(
+                st.get_stake(runtime.store(), &v.addr).unwrap(),
+                Some(v.stake)
+            );
+        }
+    }
+
+    #[test]
+    fn test_genesis_cid_query() {
+        let mut runtime = construct_runtime();
+        runtime.expect_validate_caller_any();
+        let ret = runtime
+            .call::<Actor>(Method::GenesisCid as u64, &RawBytes::default())
+            .unwrap();
+        let ret: Option<RawBytes> = ret.deserialize().unwrap();
+        let cid: Cid = ret.unwrap().deserialize().unwrap();
+        assert_eq!(cid, std_genesis().cid().unwrap());
     }
 
     #[test]
@@ -78,6 +266,7 @@ mod test {
         let validator = Address::new_id(100);
         let params = JoinParams {
             validator_net_addr: validator.to_string(),
+            signing_key: None,
         };
 
         expect_abort(
@@ -98,43 +287,47 @@ mod test {
         let start_token_value = 5_u64.pow(18);
         let params = JoinParams {
             validator_net_addr: validator.to_string(),
+            signing_key: None,
         };
 
-        // Part 1. join without enough to be activated
+        // Part 1. join without enough to become a validator
 
         // execution
         let value = TokenAmount::from_atto(start_token_value);
         runtime.set_value(value.clone());
-        runtime.set_caller(Cid::default(), caller.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller.clone());
         runtime.expect_validate_caller_any();
-        runtime
-            .call::<Actor>(
+        expect_resolve_key(&mut runtime, caller.clone());
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
                 Method::Join as u64,
                 &cbor::serialize(&params, "test").unwrap(),
-            )
-            .unwrap();
+            ),
+        );
 
         // verify state.
-        // as the value is less than min collateral, state is initiated
+        // as the value is less than the minimum stake, the deposit is rejected
         let st: State = runtime.get_state();
         assert_eq!(st.validator_set.len(), 0);
         assert_eq!(st.status, Status::Instantiated);
-        assert_eq!(st.total_stake, value);
+        assert_eq!(st.total_stake, TokenAmount::zero());
         let stake = st.get_stake(runtime.store(), &caller).unwrap();
-        assert_eq!(stake.unwrap(), value);
+        assert_eq!(stake, None);
 
-        // Part 2. miner adds stake and activates it
-        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT - start_token_value);
+        // Part 2. miner joins with enough stake and activates it
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
         runtime.set_value(value.clone());
-        runtime.set_caller(Cid::default(), caller.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller.clone());
         runtime.set_balance(TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT));
         runtime.expect_validate_caller_any();
-        runtime.expect_send(
-            Address::new_id(IPC_GATEWAY_ADDR),
-            ipc_gateway::Method::Register as u64,
-            RawBytes::default(),
-            TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
-            RawBytes::default(),
+        expect_resolve_key(&mut runtime, caller.clone());
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::register(
+                gateway_addr(),
+                TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
+            ),
             ExitCode::new(0),
         );
         runtime
@@ -164,15 +357,16 @@ mod test {
         let caller = Address::new_id(11);
         let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
         runtime.set_value(value.clone());
-        runtime.set_caller(Cid::default(), caller.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller.clone());
         runtime.set_balance(TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT));
         runtime.expect_validate_caller_any();
-        runtime.expect_send(
-            Address::new_id(IPC_GATEWAY_ADDR),
-            ipc_gateway::Method::AddStake as u64,
-            RawBytes::default(),
-            TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
-            RawBytes::default(),
+        expect_resolve_key(&mut runtime, caller.clone());
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::add_stake(
+                gateway_addr(),
+                TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
+            ),
             ExitCode::new(0),
         );
         runtime
@@ -200,29 +394,38 @@ mod test {
     }
 
     #[test]
-    fn test_leave_and_kill() {
+    fn test_join_caller_type() {
         let mut runtime = construct_runtime();
-
-        let caller = Address::new_id(10);
-        let validator = Address::new_id(100);
+        let multisig = Address::new_id(10);
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
         let params = JoinParams {
-            validator_net_addr: validator.to_string(),
+            validator_net_addr: multisig.to_string(),
+            signing_key: None,
         };
 
-        // first miner joins the subnet
-        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
-        let mut total_stake = value.clone();
+        // only accounts can become validators by default
+        runtime.set_value(value.clone());
+        runtime.set_caller(*MULTISIG_ACTOR_CODE_ID, multisig);
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_FORBIDDEN,
+            runtime.call::<Actor>(
+                Method::Join as u64,
+                &cbor::serialize(&params, "test").unwrap(),
+            ),
+        );
 
+        // unless their code is explicitly allowed
+        let mut construct_params = std_construct_param();
+        construct_params.allowed_validator_codes = vec![*MULTISIG_ACTOR_CODE_ID];
+        let mut runtime = construct_runtime_with_params(Address::new_id(1), construct_params);
         runtime.set_value(value.clone());
-        runtime.set_balance(TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT));
-        runtime.set_caller(Cid::default(), caller.clone());
+        runtime.set_balance(value.clone());
+        runtime.set_caller(*MULTISIG_ACTOR_CODE_ID, multisig);
         runtime.expect_validate_caller_any();
-        runtime.expect_send(
-            Address::new_id(IPC_GATEWAY_ADDR),
-            ipc_gateway::Method::Register as u64,
-            RawBytes::default(),
-            TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
-            RawBytes::default(),
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::register(gateway_addr(), value.clone()),
             ExitCode::new(0),
         );
         runtime
@@ -231,32 +434,26 @@ mod test {
                 &cbor::serialize(&params, "test").unwrap(),
             )
             .unwrap();
+        runtime.verify();
 
-        // Just some santity check here as it should have been tested by previous methods
-        let st: State = runtime.get_state();
-        assert_eq!(st.status, Status::Active);
-        assert_eq!(
-            st.get_stake(runtime.store(), &caller).unwrap().unwrap(),
-            TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT)
-        );
-
-        // second miner joins the subnet
-        let caller = Address::new_id(20);
-        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
-        let params = JoinParams {
-            validator_net_addr: caller.clone().to_string(),
-        };
-        total_stake = total_stake + &value;
+        // the key of accounts is resolved when they join
+        let account = Address::new_id(20);
+        let key = Address::new_secp256k1(&[1; 65]).unwrap();
         runtime.set_value(value.clone());
-        runtime.set_balance(TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT));
-        runtime.set_caller(Cid::default(), caller.clone());
+        runtime.set_balance(value.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, account);
         runtime.expect_validate_caller_any();
         runtime.expect_send(
-            Address::new_id(IPC_GATEWAY_ADDR),
-            ipc_gateway::Method::AddStake as u64,
-            RawBytes::default(),
-            TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
+            account,
+            ext::account::PUBKEY_ADDRESS_METHOD as u64,
             RawBytes::default(),
+            TokenAmount::zero(),
+            cbor::serialize(&key, "test").unwrap(),
+            ExitCode::new(0),
+        );
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::add_stake(gateway_addr(), value.clone()),
             ExitCode::new(0),
         );
         runtime
@@ -265,33 +462,58 @@ mod test {
                 &cbor::serialize(&params, "test").unwrap(),
             )
             .unwrap();
+        runtime.verify();
 
         let st: State = runtime.get_state();
-        assert_eq!(st.total_stake, total_stake);
-        assert_eq!(st.validator_set.len(), 2);
-        assert_eq!(
-            st.get_stake(runtime.store(), &caller).unwrap().unwrap(),
-            TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT)
+        assert_eq!(st.validator_set[0].key_addr, multisig);
+        assert_eq!(st.validator_set[1].key_addr, key);
+
+        // non-allowed actors can't leave either
+        let mut runtime = construct_runtime();
+        runtime.set_caller(*MULTISIG_ACTOR_CODE_ID, multisig);
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_FORBIDDEN,
+            runtime.call::<Actor>(Method::Leave as u64, &RawBytes::default()),
         );
+    }
 
-        // non-miner joins
-        let caller = Address::new_id(30);
-        let params = JoinParams {
-            validator_net_addr: caller.clone().to_string(),
-        };
-        let value = TokenAmount::from_atto(5u64.pow(18));
-        total_stake = total_stake + &value;
+    #[test]
+    fn test_signing_key() {
+        let receiver = Address::new_id(9999);
+        let root = SubnetID::from_str("/root").unwrap();
+        let subnet = SubnetID::new(&root, receiver);
+        let mut runtime = construct_runtime_with_receiver(receiver);
+        let validator = Address::new_id(10);
+        let worker = Address::new_secp256k1(&[1; 65]).unwrap();
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
 
+        // signing keys need to be secp256k1 or BLS addresses
+        let mut params = JoinParams {
+            validator_net_addr: validator.to_string(),
+            signing_key: Some(SigningKeyParams {
+                key: Address::new_id(11),
+                proof: Signature::new_secp256k1(vec![1, 2, 3, 4]),
+            }),
+        };
         runtime.set_value(value.clone());
-        runtime.set_balance(TokenAmount::from_atto(5u64.pow(18)));
-        runtime.set_caller(Cid::default(), caller.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validator);
         runtime.expect_validate_caller_any();
-        runtime.expect_send(
-            Address::new_id(IPC_GATEWAY_ADDR),
-            ipc_gateway::Method::AddStake as u64,
-            RawBytes::default(),
-            value.clone(),
-            RawBytes::default(),
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
+                Method::Join as u64,
+                &cbor::serialize(&params, "test").unwrap(),
+            ),
+        );
+
+        // the key of the account isn't resolved if a signing key is registered
+        params.signing_key = Some(key_params(&mut runtime, validator, worker, true));
+        runtime.set_balance(value.clone());
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::register(gateway_addr(), value.clone()),
             ExitCode::new(0),
         );
         runtime
@@ -300,131 +522,2523 @@ mod test {
                 &cbor::serialize(&params, "test").unwrap(),
             )
             .unwrap();
+        runtime.verify();
         let st: State = runtime.get_state();
-        assert_eq!(st.total_stake, total_stake);
-        assert_eq!(st.validator_set.len(), 2);
-        assert_eq!(
-            st.get_stake(runtime.store(), &caller).unwrap().unwrap(),
-            value
-        );
+        assert_eq!(st.validator_set[0].key_addr, worker);
 
-        // one miner leaves the subnet
-        let caller = Address::new_id(10);
-        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
-        total_stake = total_stake - &value;
-        runtime.set_value(value.clone());
-        runtime.set_caller(Cid::default(), caller.clone());
+        // checkpoints are signed with the signing key
+        let ch = signed_checkpoint(&subnet, st.check_period, st.genesis_cid);
+        submit_signed_checkpoint(&mut runtime, validator, worker, &ch);
+
+        // the new key only takes effect after the rotation delay
+        let rotated = Address::new_secp256k1(&[2; 65]).unwrap();
+        runtime.set_epoch(20);
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validator);
         runtime.expect_validate_caller_any();
-        runtime.expect_send(
-            Address::new_id(IPC_GATEWAY_ADDR),
-            ipc_gateway::Method::ReleaseStake as u64,
-            RawBytes::serialize(FundParams {
-                value: value.clone(),
-            })
-            .unwrap(),
-            TokenAmount::zero(),
-            RawBytes::default(),
-            ExitCode::new(0),
-        );
+        let params = key_params(&mut runtime, validator, rotated, true);
         runtime
-            .call::<Actor>(Method::Leave as u64, &RawBytes::default())
+            .call::<Actor>(
+                Method::RotateSigningKey as u64,
+                &RawBytes::serialize(params).unwrap(),
+            )
             .unwrap();
-
         let st: State = runtime.get_state();
-        assert_eq!(st.validator_set.len(), 1);
-        assert_eq!(st.status, Status::Active);
-        assert_eq!(st.total_stake, total_stake);
-        assert_eq!(
-            st.get_stake(runtime.store(), &caller).unwrap().unwrap(),
-            TokenAmount::zero()
+        assert_eq!(st.validator_set[0].key_addr, worker);
+        assert_eq!(st.validator_set[0].signing_keys(20), vec![worker]);
+
+        // and only one rotation can be pending
+        let other_key = Address::new_secp256k1(&[3; 65]).unwrap();
+        runtime.expect_validate_caller_any();
+        let params = key_params(&mut runtime, validator, other_key, true);
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            runtime.call::<Actor>(
+                Method::RotateSigningKey as u64,
+                &RawBytes::serialize(params).unwrap(),
+            ),
         );
 
-        // subnet can't be killed if there are still miners
+        // rotations need a proof of possession of the new key
+        runtime.expect_validate_caller_any();
+        let params = key_params(&mut runtime, validator, other_key, false);
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
+                Method::RotateSigningKey as u64,
+                &RawBytes::serialize(params).unwrap(),
+            ),
+        );
+
+        // the current and pending keys of a validator can't be used by others
+        let other = Address::new_id(20);
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, other);
+        for key in [worker, rotated] {
+            runtime.expect_validate_caller_any();
+            let params = JoinParams {
+                validator_net_addr: other.to_string(),
+                signing_key: Some(key_params(&mut runtime, other, key, true)),
+            };
+            expect_abort(
+                ExitCode::USR_ILLEGAL_ARGUMENT,
+                runtime.call::<Actor>(
+                    Method::Join as u64,
+                    &cbor::serialize(&params, "test").unwrap(),
+                ),
+            );
+        }
+        runtime.verify();
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validator);
+
+        let ch = signed_checkpoint(&subnet, 2 * st.check_period, ch.cid());
+        submit_signed_checkpoint(&mut runtime, validator, worker, &ch);
+
+        // both keys are accepted during the overlap window
+        let effective_epoch = 20 + SIGNING_KEY_ROTATION_DELAY;
+        runtime.set_epoch(effective_epoch);
+        let ch = signed_checkpoint(&subnet, 3 * st.check_period, ch.cid());
+        runtime.expect_verify_signature(ExpectedVerifySig {
+            sig: Signature::new_secp256k1(vec![1, 2, 3, 4]),
+            signer: rotated,
+            plaintext: ch.cid().to_bytes(),
+            result: Err(anyhow::anyhow!("invalid signature")),
+        });
+        submit_signed_checkpoint(&mut runtime, validator, worker, &ch);
+
+        // the old key is retired after the overlap window
+        runtime.set_epoch(effective_epoch + st.check_period);
+        let prev = ch.cid();
+        let ch = signed_checkpoint(&subnet, 4 * st.check_period, prev);
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validator);
         runtime.expect_validate_caller_any();
+        runtime.expect_verify_signature(ExpectedVerifySig {
+            sig: Signature::new_secp256k1(vec![1, 2, 3, 4]),
+            signer: rotated,
+            plaintext: ch.cid().to_bytes(),
+            result: Err(anyhow::anyhow!("invalid signature")),
+        });
         expect_abort(
             ExitCode::USR_ILLEGAL_STATE,
-            runtime.call::<Actor>(Method::Kill as u64, &RawBytes::default()),
+            runtime.call::<Actor>(
+                Method::SubmitCheckpoint as u64,
+                &cbor::serialize(&ch, "test").unwrap(),
+            ),
         );
+        runtime.verify();
+        submit_signed_checkpoint(&mut runtime, validator, rotated, &ch);
 
-        // // next miner inactivates the subnet
-        let caller = Address::new_id(20);
-        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
-        total_stake = total_stake - &value;
-        runtime.set_value(value.clone());
-        runtime.set_caller(Cid::default(), caller.clone());
+        // the account of the signing key can submit checkpoints for the validator
+        let worker_account = Address::new_id(12);
+        let ch = signed_checkpoint(&subnet, 5 * st.check_period, ch.cid());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, worker_account);
         runtime.expect_validate_caller_any();
-        runtime.expect_send(
-            Address::new_id(IPC_GATEWAY_ADDR),
-            ipc_gateway::Method::ReleaseStake as u64,
-            RawBytes::serialize(FundParams {
-                value: value.clone(),
-            })
-            .unwrap(),
-            TokenAmount::zero(),
-            RawBytes::default(),
+        expect_pubkey(&mut runtime, worker_account, rotated);
+        runtime.expect_verify_signature(ExpectedVerifySig {
+            sig: Signature::new_secp256k1(vec![1, 2, 3, 4]),
+            signer: rotated,
+            plaintext: ch.cid().to_bytes(),
+            result: Ok(()),
+        });
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::commit_child_checkpoint(gateway_addr(), &ch).unwrap(),
             ExitCode::new(0),
         );
         runtime
-            .call::<Actor>(Method::Leave as u64, &RawBytes::default())
+            .call::<Actor>(
+                Method::SubmitCheckpoint as u64,
+                &cbor::serialize(&ch, "test").unwrap(),
+            )
             .unwrap();
-
+        runtime.verify();
         let st: State = runtime.get_state();
-        assert_eq!(st.validator_set.len(), 0);
-        assert_eq!(st.status, Status::Inactive);
-        assert_eq!(st.total_stake, total_stake);
-        assert_eq!(
-            st.get_stake(runtime.store(), &caller).unwrap().unwrap(),
-            TokenAmount::zero()
-        );
+        let commit = st
+            .get_commit(runtime.store(), &(5 * st.check_period))
+            .unwrap()
+            .unwrap();
+        assert_eq!(commit.signers, vec![validator]);
 
-        // last joiner gets the stake and kills the subnet
-        let caller = Address::new_id(30);
-        let value = TokenAmount::from_atto(5u64.pow(18));
-        total_stake = total_stake - &value;
+        // rotations are recorded in the key history
+        runtime.expect_validate_caller_any();
+        let history: Vec<KeyRotation> = runtime
+            .call::<Actor>(
+                Method::SigningKeyHistory as u64,
+                &RawBytes::serialize(validator).unwrap(),
+            )
+            .unwrap()
+            .deserialize()
+            .unwrap();
+        assert_eq!(
+            history,
+            vec![KeyRotation {
+                old_key: worker,
+                new_key: rotated,
+                epoch: effective_epoch,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_permissioned_subnet() {
+        let receiver = Address::new_id(9999);
+        let owner = Address::new_id(1);
+        let validators = vec![Address::new_id(10), Address::new_id(20)];
+        let candidate = Address::new_id(30);
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+
+        // only a permissioned subnet has an allow-list
+        let mut runtime = setup_subnet(receiver, &validators);
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::UpdateAllowList,
+            RawBytes::serialize(AllowListParams {
+                addr: candidate,
+                allowed: true,
+            })
+            .unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+
+        let mut params = std_construct_param();
+        params.permissioning = Some(Permissioning {
+            owner: Some(owner),
+            allow_list: validators.clone(),
+        });
+        let mut runtime = setup_subnet_with_params(receiver, params, &validators);
+
+        // callers not in the allow-list can't join
+        runtime.set_balance(value.clone());
+        runtime.set_value(value.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, candidate);
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_FORBIDDEN,
+            runtime.call::<Actor>(
+                Method::Join as u64,
+                &cbor::serialize(
+                    &JoinParams {
+                        validator_net_addr: candidate.to_string(),
+                        signing_key: None,
+                    },
+                    "test",
+                )
+                .unwrap(),
+            ),
+        );
+        call_method(
+            &mut runtime,
+            candidate,
+            Method::UpdateAllowList,
+            RawBytes::serialize(AllowListParams {
+                addr: candidate,
+                allowed: true,
+            })
+            .unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_FORBIDDEN,
+        );
+
+        // updates from validators need a majority
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::UpdateAllowList,
+            RawBytes::serialize(AllowListParams {
+                addr: candidate,
+                allowed: true,
+            })
+            .unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        assert_eq!(allow_list(&mut runtime), validators);
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::UpdateAllowList,
+            RawBytes::serialize(AllowListParams {
+                addr: candidate,
+                allowed: true,
+            })
+            .unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+        call_method(
+            &mut runtime,
+            validators[1],
+            Method::UpdateAllowList,
+            RawBytes::serialize(AllowListParams {
+                addr: candidate,
+                allowed: true,
+            })
+            .unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        assert_eq!(
+            allow_list(&mut runtime),
+            vec![validators[0], validators[1], candidate]
+        );
+        join_subnet(
+            &mut runtime,
+            candidate,
+            value.clone(),
+            Some((ipc_gateway::Method::AddStake as u64, value.clone())),
+        )
+        .unwrap();
+
+        // updates from the owner are applied straight away
+        call_method(
+            &mut runtime,
+            owner,
+            Method::UpdateAllowList,
+            RawBytes::serialize(AllowListParams {
+                addr: validators[0],
+                allowed: false,
+            })
+            .unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        assert_eq!(allow_list(&mut runtime), vec![validators[1], candidate]);
+        runtime.set_value(value);
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validators[0]);
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_FORBIDDEN,
+            runtime.call::<Actor>(
+                Method::Join as u64,
+                &cbor::serialize(
+                    &JoinParams {
+                        validator_net_addr: validators[0].to_string(),
+                        signing_key: None,
+                    },
+                    "test",
+                )
+                .unwrap(),
+            ),
+        );
+
+        // the allow-list is frozen once the subnet is terminating
+        let mut params = std_construct_param();
+        params.permissioning = Some(Permissioning {
+            owner: Some(owner),
+            allow_list: vec![],
+        });
+        let mut runtime = construct_runtime_with_params(receiver, params);
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, owner);
+        runtime.set_value(TokenAmount::zero());
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::kill(gateway_addr()),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(Method::Kill as u64, &RawBytes::default())
+            .unwrap();
+        call_method(
+            &mut runtime,
+            owner,
+            Method::UpdateAllowList,
+            RawBytes::serialize(AllowListParams {
+                addr: candidate,
+                allowed: true,
+            })
+            .unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+    }
+
+    #[test]
+    fn test_validator_removal() {
+        let receiver = Address::new_id(9999);
+        let validators = vec![
+            Address::new_id(10),
+            Address::new_id(20),
+            Address::new_id(30),
+        ];
+        let stake = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        let mut runtime = setup_subnet(receiver, &validators);
+        runtime.set_epoch(100);
+
+        // only validators can propose and be targeted by removals
+        call_method(
+            &mut runtime,
+            Address::new_id(100),
+            Method::ProposeRemoval,
+            RawBytes::serialize(validators[2]).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_FORBIDDEN,
+        );
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::ProposeRemoval,
+            RawBytes::serialize(Address::new_id(100)).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+        );
+
+        // removals need a majority of the voting power
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::ProposeRemoval,
+            RawBytes::serialize(validators[2]).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::ProposeRemoval,
+            RawBytes::serialize(validators[2]).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+        let st: State = runtime.get_state();
+        assert!(st.is_validator(&validators[2]));
+
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::release_stake(gateway_addr(), stake.clone()).unwrap(),
+            ExitCode::new(0),
+        );
+        call_method(
+            &mut runtime,
+            validators[1],
+            Method::ProposeRemoval,
+            RawBytes::serialize(validators[2]).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        runtime.verify();
+        let st: State = runtime.get_state();
+        assert!(!st.is_validator(&validators[2]));
+        assert_eq!(st.total_stake, stake.clone() * 2);
+        assert_eq!(
+            st.get_stake(runtime.store(), &validators[2]).unwrap(),
+            Some(TokenAmount::zero())
+        );
+
+        // the stake of the removed validator goes through unbonding
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validators[2]);
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            runtime.call::<Actor>(Method::WithdrawUnbonded as u64, &RawBytes::default()),
+        );
+
+        runtime.set_epoch(100 + UNBONDING_PERIOD);
+        runtime.set_balance(stake.clone());
+        runtime.expect_validate_caller_any();
+        runtime.expect_send(
+            validators[2],
+            METHOD_SEND,
+            RawBytes::default(),
+            stake,
+            RawBytes::default(),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(Method::WithdrawUnbonded as u64, &RawBytes::default())
+            .unwrap();
+        runtime.verify();
+    }
+
+    #[test]
+    fn test_outbox() {
+        let mut runtime = construct_runtime();
+        let validators = vec![Address::new_id(10), Address::new_id(20)];
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+
+        // failed messages to the gateway are kept in the outbox
+        for v in validators.iter() {
+            runtime.set_value(value.clone());
+            runtime.set_balance(value.clone());
+            runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, *v);
+            runtime.expect_validate_caller_any();
+            expect_resolve_key(&mut runtime, *v);
+            // messages after a failed one are not sent
+            expect_gateway_send(
+                &mut runtime,
+                ext::gateway::register(gateway_addr(), value.clone()),
+                ExitCode::USR_ILLEGAL_STATE,
+            );
+            runtime
+                .call::<Actor>(
+                    Method::Join as u64,
+                    &cbor::serialize(
+                        &JoinParams {
+                            validator_net_addr: v.to_string(),
+                            signing_key: None,
+                        },
+                        "test",
+                    )
+                    .unwrap(),
+                )
+                .unwrap();
+            runtime.verify();
+        }
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Active);
+        assert_eq!(st.outbox.len(), 2);
+        let status = collateral_status(&mut runtime);
+        assert_eq!(status.local, value.clone() * 2);
+        assert_eq!(status.gateway, TokenAmount::zero());
+        assert_eq!(status.pending_messages, 2);
+
+        // the registration is never dropped
+        runtime.set_epoch(OUTBOX_RETRY_PERIOD);
+        runtime.set_value(TokenAmount::zero());
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::register(gateway_addr(), value.clone()),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+        let status: CollateralStatus = runtime
+            .call::<Actor>(Method::FlushOutbox as u64, &RawBytes::default())
+            .unwrap()
+            .deserialize()
+            .unwrap();
+        runtime.verify();
+        assert_eq!(status.pending_messages, 2);
+        assert_eq!(status.dropped_messages, 0);
+
+        // the outbox is delivered in order once the gateway accepts the messages
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::register(gateway_addr(), value.clone()),
+            ExitCode::new(0),
+        );
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::add_stake(gateway_addr(), value.clone()),
+            ExitCode::new(0),
+        );
+        let status: CollateralStatus = runtime
+            .call::<Actor>(Method::FlushOutbox as u64, &RawBytes::default())
+            .unwrap()
+            .deserialize()
+            .unwrap();
+        runtime.verify();
+        assert_eq!(
+            status,
+            CollateralStatus {
+                local: value.clone() * 2,
+                gateway: value.clone() * 2,
+                pending_messages: 0,
+                dropped_messages: 0,
+                consistent: true,
+            }
+        );
+
+        // the subnet is only unregistered once the gateway gets the kill message
+        runtime.set_balance(TokenAmount::zero());
+        for v in validators.iter() {
+            leave_subnet(&mut runtime, *v, value.clone(), false).unwrap();
+        }
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validators[0]);
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::kill(gateway_addr()),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+        runtime
+            .call::<Actor>(Method::Kill as u64, &RawBytes::default())
+            .unwrap();
+        runtime.verify();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Terminating);
+        assert!(!st.unregistered);
+        assert_eq!(collateral_status(&mut runtime).pending_messages, 1);
+
+        // messages failing for too long are dropped, together with their burns
+        let mut params = std_construct_param();
+        params.leaving_penalty = LeavingPenalty {
+            numerator: 1,
+            denominator: 4,
+            destination: PenaltyDestination::Burn,
+        };
+        let mut runtime = setup_subnet_with_params(Address::new_id(9999), params, &validators);
+        runtime.set_epoch(100);
+        runtime.set_value(TokenAmount::zero());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validators[0]);
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::release_stake(gateway_addr(), value.clone()).unwrap(),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+        runtime
+            .call::<Actor>(Method::Leave as u64, &RawBytes::default())
+            .unwrap();
+        runtime.verify();
+        assert_eq!(collateral_status(&mut runtime).pending_messages, 2);
+
+        runtime.set_epoch(100 + OUTBOX_RETRY_PERIOD - 1);
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::release_stake(gateway_addr(), value.clone()).unwrap(),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+        runtime
+            .call::<Actor>(Method::FlushOutbox as u64, &RawBytes::default())
+            .unwrap();
+        runtime.verify();
+        assert_eq!(collateral_status(&mut runtime).pending_messages, 2);
+
+        runtime.set_epoch(100 + OUTBOX_RETRY_PERIOD);
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::release_stake(gateway_addr(), value.clone()).unwrap(),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+        let status: CollateralStatus = runtime
+            .call::<Actor>(Method::FlushOutbox as u64, &RawBytes::default())
+            .unwrap()
+            .deserialize()
+            .unwrap();
+        runtime.verify();
+        assert_eq!(status.pending_messages, 0);
+        assert_eq!(status.dropped_messages, 1);
+        assert!(!status.consistent);
+    }
+
+    #[test]
+    fn test_sync_stake() {
+        let receiver = Address::new_id(9999);
+        let validators = vec![Address::new_id(10), Address::new_id(20)];
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+
+        // only registered subnets are synced
+        let mut runtime = construct_runtime_with_receiver(receiver);
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            runtime.call::<Actor>(Method::SyncStake as u64, &RawBytes::default()),
+        );
+
+        let mut runtime = setup_subnet(receiver, &validators);
+        #[cfg(feature = "gateway-extensions")]
+        {
+            let root = SubnetID::from_str("/root").unwrap();
+            let subnet = SubnetID::new(&root, receiver);
+
+            // the subnet has no stake to make up for the collateral missing in the gateway
+            expect_get_collateral(&mut runtime, &subnet, value.clone());
+            let status = sync_stake(&mut runtime, None);
+            assert_eq!(status.gateway, value.clone());
+            assert!(!status.consistent);
+
+            // the gateway recorded more collateral than the subnet holds
+            expect_get_collateral(&mut runtime, &subnet, value.clone() * 3);
+            let status = sync_stake(
+                &mut runtime,
+                Some(ext::gateway::release_stake(gateway_addr(), value.clone()).unwrap()),
+            );
+            assert_eq!(status.gateway, value.clone() * 2);
+            assert!(status.consistent);
+        }
+
+        // stake that never reached the gateway is added again
+        let new_validator = Address::new_id(30);
         runtime.set_value(value.clone());
-        runtime.set_caller(Cid::default(), caller.clone());
+        runtime.set_balance(value.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, new_validator);
+        runtime.expect_validate_caller_any();
+        expect_resolve_key(&mut runtime, new_validator);
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::add_stake(gateway_addr(), value.clone()),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+        runtime
+            .call::<Actor>(
+                Method::Join as u64,
+                &cbor::serialize(
+                    &JoinParams {
+                        validator_net_addr: new_validator.to_string(),
+                        signing_key: None,
+                    },
+                    "test",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        runtime.verify();
+
+        runtime.set_epoch(OUTBOX_RETRY_PERIOD);
+        runtime.set_value(TokenAmount::zero());
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::add_stake(gateway_addr(), value.clone()),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+        let status: CollateralStatus = runtime
+            .call::<Actor>(Method::FlushOutbox as u64, &RawBytes::default())
+            .unwrap()
+            .deserialize()
+            .unwrap();
+        runtime.verify();
+        assert_eq!(status.dropped_messages, 1);
+        assert!(!status.consistent);
+        let st: State = runtime.get_state();
+        assert_eq!(st.unforwarded_stake, value.clone());
+
+        #[cfg(feature = "gateway-extensions")]
+        expect_get_collateral(
+            &mut runtime,
+            &SubnetID::new(&SubnetID::from_str("/root").unwrap(), receiver),
+            value.clone() * 2,
+        );
+        let status = sync_stake(
+            &mut runtime,
+            Some(ext::gateway::add_stake(gateway_addr(), value.clone())),
+        );
+        assert_eq!(status.gateway, value.clone() * 3);
+        assert!(status.consistent);
+        let st: State = runtime.get_state();
+        assert_eq!(st.unforwarded_stake, TokenAmount::zero());
+    }
+
+    #[test]
+    fn test_stake_invariants() {
+        let receiver = Address::new_id(9999);
+        let mut runtime = construct_runtime_with_receiver(receiver);
+        let stakers: Vec<Address> = (10..15).map(Address::new_id).collect();
+        let min = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+
+        // deterministic pseudo-random sequence of joins and leaves
+        let mut seed: u64 = 42;
+        let mut next = |n: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % n
+        };
+        for _ in 0..100 {
+            let caller = stakers[next(stakers.len() as u64) as usize];
+            let st: State = runtime.get_state();
+            let stake = st
+                .get_stake(runtime.store(), &caller)
+                .unwrap()
+                .unwrap_or_else(TokenAmount::zero);
+            if stake > TokenAmount::zero() && next(3) == 0 {
+                leave_subnet(&mut runtime, caller, stake, false).unwrap();
+            } else {
+                let amount = min.clone() * (1 + next(3));
+                let method = if st.status == Status::Instantiated {
+                    (
+                        ipc_gateway::Method::Register as u64,
+                        st.total_stake.clone() + &amount,
+                    )
+                } else {
+                    (ipc_gateway::Method::AddStake as u64, amount.clone())
+                };
+                runtime.set_balance(amount.clone());
+                join_subnet(&mut runtime, caller, amount, Some(method)).unwrap();
+            }
+
+            // the stake table, the validator set and the gateway agree on the stake
+            let st: State = runtime.get_state();
+            let mut sum = TokenAmount::zero();
+            for s in stakers.iter() {
+                let stake = st
+                    .get_stake(runtime.store(), s)
+                    .unwrap()
+                    .unwrap_or_else(TokenAmount::zero);
+                assert_eq!(st.is_validator(s), stake > TokenAmount::zero());
+                sum += stake;
+            }
+            assert_eq!(st.total_stake, sum);
+            let status = collateral_status(&mut runtime);
+            assert_eq!(status.gateway, sum);
+            assert!(status.consistent);
+        }
+
+        // no corrections are needed when the gateway agrees with the subnet
+        #[cfg(feature = "gateway-extensions")]
+        {
+            let st: State = runtime.get_state();
+            let root = SubnetID::from_str("/root").unwrap();
+            let subnet = SubnetID::new(&root, receiver);
+            expect_get_collateral(&mut runtime, &subnet, st.total_stake.clone());
+        }
+        let status = sync_stake(&mut runtime, None);
+        assert_eq!(status.pending_messages, 0);
+        assert!(status.consistent);
+    }
+
+    #[test]
+    fn test_fees() {
+        let receiver = Address::new_id(9999);
+        let validator = Address::new_id(10);
+
+        // fees cannot be negative
+        let mut params = std_construct_param();
+        params.fee_config = FeeConfig {
+            checkpoint_fee: TokenAmount::from_atto(-10),
+            cross_msg_fee: TokenAmount::from_atto(5),
+            destination: FeeDestination::RewardPool,
+        };
+        let mut runtime = MockRuntime::new(receiver, *INIT_ACTOR_ADDR);
+        runtime.expect_validate_caller_addr(vec![*INIT_ACTOR_ADDR]);
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
+                Method::Constructor as u64,
+                &cbor::serialize(&params, "test").unwrap(),
+            ),
+        );
+
+        // fees are requested from the gateway on commitment,
+        // so they are rejected if the gateway can't collect them
+        params.fee_config.checkpoint_fee = TokenAmount::from_atto(10);
+        #[cfg(not(feature = "gateway-extensions"))]
+        {
+            let mut runtime = MockRuntime::new(receiver, *INIT_ACTOR_ADDR);
+            runtime.expect_validate_caller_addr(vec![*INIT_ACTOR_ADDR]);
+            expect_abort(
+                ExitCode::USR_ILLEGAL_ARGUMENT,
+                runtime.call::<Actor>(
+                    Method::Constructor as u64,
+                    &cbor::serialize(&params, "test").unwrap(),
+                ),
+            );
+        }
+        #[cfg(feature = "gateway-extensions")]
+        {
+            let root = SubnetID::from_str("/root").unwrap();
+            let subnet = SubnetID::new(&root, receiver);
+            let mut runtime = setup_subnet_with_params(receiver, params.clone(), &[validator]);
+            call_method(
+                &mut runtime,
+                gateway_addr(),
+                Method::NotifyFund,
+                RawBytes::serialize(FundParams {
+                    value: TokenAmount::from_atto(100),
+                })
+                .unwrap(),
+                TokenAmount::zero(),
+                ExitCode::OK,
+            );
+            let st: State = runtime.get_state();
+            let mut ch = signed_checkpoint(&subnet, st.check_period, st.genesis_cid);
+            ch.data.cross_msgs = vec![CrossMsgMeta {
+                from: subnet.clone(),
+                to: root.clone(),
+                msgs_cid: st.genesis_cid.into(),
+                nonce: 0,
+                value: TokenAmount::from_atto(10),
+            }];
+            runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validator);
+            runtime.expect_validate_caller_any();
+            runtime.expect_verify_signature(ExpectedVerifySig {
+                sig: Signature::new_secp256k1(vec![1, 2, 3, 4]),
+                signer: validator,
+                plaintext: ch.cid().to_bytes(),
+                result: Ok(()),
+            });
+            expect_gateway_send(
+                &mut runtime,
+                ext::gateway::commit_child_checkpoint(gateway_addr(), &ch).unwrap(),
+                ExitCode::new(0),
+            );
+            expect_gateway_send(
+                &mut runtime,
+                ext::gateway::collect_fees(gateway_addr(), TokenAmount::from_atto(15)).unwrap(),
+                ExitCode::new(0),
+            );
+            runtime
+                .call::<Actor>(
+                    Method::SubmitCheckpoint as u64,
+                    &cbor::serialize(&ch, "test").unwrap(),
+                )
+                .unwrap();
+            runtime.verify();
+        }
+
+        // only the gateway pays the fees, which go to the reward pool
+        params.fee_config = FeeConfig::default();
+        let mut runtime = setup_subnet_with_params(receiver, params.clone(), &[validator]);
+        let fee = TokenAmount::from_atto(15);
+        call_method(
+            &mut runtime,
+            validator,
+            Method::CollectFees,
+            RawBytes::default(),
+            fee.clone(),
+            ExitCode::USR_FORBIDDEN,
+        );
+        call_method(
+            &mut runtime,
+            gateway_addr(),
+            Method::CollectFees,
+            RawBytes::default(),
+            fee.clone(),
+            ExitCode::OK,
+        );
+        let st: State = runtime.get_state();
+        assert_eq!(st.reward_pool, fee);
+        assert_eq!(st.collected_fees, fee);
+
+        // or to the treasury
+        params.fee_config.destination = FeeDestination::Treasury;
+        let mut runtime = setup_subnet_with_params(receiver, params, &[validator]);
+        call_method(
+            &mut runtime,
+            gateway_addr(),
+            Method::CollectFees,
+            RawBytes::default(),
+            fee.clone(),
+            ExitCode::OK,
+        );
+        let st: State = runtime.get_state();
+        assert_eq!(st.reward_pool, TokenAmount::zero());
+        assert_eq!(st.treasury, fee);
+        assert_eq!(st.collected_fees, fee);
+    }
+
+    #[test]
+    fn test_treasury() {
+        let receiver = Address::new_id(9999);
+        let validators = vec![
+            Address::new_id(10),
+            Address::new_id(20),
+            Address::new_id(30),
+        ];
+        let donor = Address::new_id(100);
+        let to = Address::new_id(200);
+        let mut runtime = setup_subnet(receiver, &validators);
+
+        // anyone can donate to the treasury
+        let donation = TokenAmount::from_atto(1000);
+        call_method(
+            &mut runtime,
+            donor,
+            Method::Donate,
+            RawBytes::default(),
+            TokenAmount::zero(),
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+        );
+        call_method(
+            &mut runtime,
+            donor,
+            Method::Donate,
+            RawBytes::default(),
+            donation.clone(),
+            ExitCode::OK,
+        );
+        let st: State = runtime.get_state();
+        assert_eq!(st.treasury, donation);
+
+        // only validators spend the treasury, and never more than it holds
+        let spend = SpendParams {
+            to,
+            amount: TokenAmount::from_atto(400),
+        };
+        call_method(
+            &mut runtime,
+            donor,
+            Method::ProposeSpend,
+            RawBytes::serialize(&spend).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_FORBIDDEN,
+        );
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::ProposeSpend,
+            RawBytes::serialize(&SpendParams {
+                to,
+                amount: TokenAmount::from_atto(2000),
+            })
+            .unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+        );
+
+        // spends need a majority of the voting power
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::ProposeSpend,
+            RawBytes::serialize(&spend).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::ProposeSpend,
+            RawBytes::serialize(&spend).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+        let st: State = runtime.get_state();
+        assert_eq!(st.treasury, donation);
+
+        runtime.set_balance(donation.clone());
+        runtime.expect_send(
+            to,
+            METHOD_SEND,
+            RawBytes::default(),
+            spend.amount.clone(),
+            RawBytes::default(),
+            ExitCode::new(0),
+        );
+        call_method(
+            &mut runtime,
+            validators[1],
+            Method::ProposeSpend,
+            RawBytes::serialize(&spend).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        let st: State = runtime.get_state();
+        assert_eq!(st.treasury, donation.clone() - &spend.amount);
+
+        // the treasury doesn't block the kill and is returned to the beneficiary
+        let beneficiary = Address::new_id(300);
+        let mut params = std_construct_param();
+        params.treasury_beneficiary = Some(beneficiary);
+        let mut runtime = construct_runtime_with_params(receiver, params);
+        call_method(
+            &mut runtime,
+            donor,
+            Method::Donate,
+            RawBytes::default(),
+            donation.clone(),
+            ExitCode::OK,
+        );
+
+        // and so is the reward pool that was never distributed
+        let funds = TokenAmount::from_atto(50);
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, donor);
+        runtime.set_value(funds.clone());
+        runtime.expect_validate_caller_any();
+        runtime
+            .call::<Actor>(Method::FundRewards as u64, &RawBytes::default())
+            .unwrap();
+
+        runtime.set_balance(donation.clone() + &funds);
+        runtime.set_value(TokenAmount::zero());
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::kill(gateway_addr()),
+            ExitCode::new(0),
+        );
+        runtime.expect_send(
+            beneficiary,
+            METHOD_SEND,
+            RawBytes::default(),
+            donation.clone() + &funds,
+            RawBytes::default(),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(Method::Kill as u64, &RawBytes::default())
+            .unwrap();
+        runtime.verify();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Terminating);
+        assert_eq!(st.treasury, TokenAmount::zero());
+        assert_eq!(st.reward_pool, TokenAmount::zero());
+
+        // no more donations are accepted once the subnet is terminating
+        call_method(
+            &mut runtime,
+            donor,
+            Method::Donate,
+            RawBytes::default(),
+            donation,
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+    }
+
+    #[test]
+    fn test_leave_and_kill() {
+        let mut runtime = construct_runtime();
+
+        let caller = Address::new_id(10);
+        let validator = Address::new_id(100);
+        let params = JoinParams {
+            validator_net_addr: validator.to_string(),
+            signing_key: None,
+        };
+
+        // first miner joins the subnet
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        let mut total_stake = value.clone();
+
+        runtime.set_value(value.clone());
+        runtime.set_balance(TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT));
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller.clone());
+        runtime.expect_validate_caller_any();
+        expect_resolve_key(&mut runtime, caller.clone());
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::register(
+                gateway_addr(),
+                TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
+            ),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(
+                Method::Join as u64,
+                &cbor::serialize(&params, "test").unwrap(),
+            )
+            .unwrap();
+
+        // Just some santity check here as it should have been tested by previous methods
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Active);
+        assert_eq!(
+            st.get_stake(runtime.store(), &caller).unwrap().unwrap(),
+            TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT)
+        );
+
+        // second miner joins the subnet
+        let caller = Address::new_id(20);
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        let params = JoinParams {
+            validator_net_addr: caller.clone().to_string(),
+            signing_key: None,
+        };
+        total_stake = total_stake + &value;
+        runtime.set_value(value.clone());
+        runtime.set_balance(TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT));
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller.clone());
+        runtime.expect_validate_caller_any();
+        expect_resolve_key(&mut runtime, caller.clone());
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::add_stake(
+                gateway_addr(),
+                TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
+            ),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(
+                Method::Join as u64,
+                &cbor::serialize(&params, "test").unwrap(),
+            )
+            .unwrap();
+
+        let st: State = runtime.get_state();
+        assert_eq!(st.total_stake, total_stake);
+        assert_eq!(st.validator_set.len(), 2);
+        assert_eq!(
+            st.get_stake(runtime.store(), &caller).unwrap().unwrap(),
+            TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT)
+        );
+
+        // stakers below the minimum stake can't join
+        let caller = Address::new_id(30);
+        let params = JoinParams {
+            validator_net_addr: caller.clone().to_string(),
+            signing_key: None,
+        };
+        let value = TokenAmount::from_atto(5u64.pow(18));
+
+        runtime.set_value(value.clone());
+        runtime.set_balance(TokenAmount::from_atto(5u64.pow(18)));
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller.clone());
+        runtime.expect_validate_caller_any();
+        expect_resolve_key(&mut runtime, caller.clone());
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
+                Method::Join as u64,
+                &cbor::serialize(&params, "test").unwrap(),
+            ),
+        );
+        let st: State = runtime.get_state();
+        assert_eq!(st.total_stake, total_stake);
+        assert_eq!(st.validator_set.len(), 2);
+        assert_eq!(st.get_stake(runtime.store(), &caller).unwrap(), None);
+
+        // one miner leaves the subnet
+        let caller = Address::new_id(10);
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        total_stake = total_stake - &value;
+        runtime.set_value(value.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller.clone());
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::release_stake(gateway_addr(), value.clone()).unwrap(),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(Method::Leave as u64, &RawBytes::default())
+            .unwrap();
+
+        let st: State = runtime.get_state();
+        assert_eq!(st.validator_set.len(), 1);
+        assert_eq!(st.status, Status::Active);
+        assert_eq!(st.total_stake, total_stake);
+        assert_eq!(
+            st.get_stake(runtime.store(), &caller).unwrap().unwrap(),
+            TokenAmount::zero()
+        );
+
+        // subnet can't be killed if there are still miners
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            runtime.call::<Actor>(Method::Kill as u64, &RawBytes::default()),
+        );
+
+        // // next miner inactivates the subnet
+        let caller = Address::new_id(20);
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        total_stake = total_stake - &value;
+        runtime.set_value(value.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller.clone());
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::release_stake(gateway_addr(), value.clone()).unwrap(),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(Method::Leave as u64, &RawBytes::default())
+            .unwrap();
+
+        let st: State = runtime.get_state();
+        assert_eq!(st.validator_set.len(), 0);
+        assert_eq!(st.status, Status::Inactive);
+        assert_eq!(st.total_stake, total_stake);
+        assert_eq!(
+            st.get_stake(runtime.store(), &caller).unwrap().unwrap(),
+            TokenAmount::zero()
+        );
+
+        // anyone can kill the subnet once all validators left
+        let caller = Address::new_id(30);
+        let value = TokenAmount::from_atto(5u64.pow(18));
+        runtime.set_balance(TokenAmount::zero());
+        runtime.set_value(value.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller.clone());
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::kill(gateway_addr()),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(Method::Kill as u64, &RawBytes::default())
+            .unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.total_stake, TokenAmount::zero());
+        assert_eq!(st.status, Status::Terminating);
+        assert_eq!(st.unregistered, true);
+
+        // the gateway confirmed the unregistration so the kill can be finalized
+        runtime.expect_validate_caller_any();
+        runtime
+            .call::<Actor>(Method::FinalizeKill as u64, &RawBytes::default())
+            .unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Killed);
+
+        // killed subnets refuse any further state change
+        runtime.set_balance(value.clone());
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            join_subnet(&mut runtime, caller, value.clone(), None),
+        );
+        runtime.set_balance(TokenAmount::zero());
+        for method in [Method::Leave, Method::Kill, Method::FinalizeKill] {
+            runtime.expect_validate_caller_any();
+            expect_abort(
+                ExitCode::USR_ILLEGAL_STATE,
+                runtime.call::<Actor>(method as u64, &RawBytes::default()),
+            );
+        }
+        let subnet = SubnetID::new(&st.parent_id, Address::new_id(1));
+        let proof = FraudProof {
+            signer: caller,
+            first: signed_checkpoint(&subnet, 10, st.genesis_cid),
+            second: signed_checkpoint(&subnet, 10, Cid::default()),
+        };
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            runtime.call::<Actor>(
+                Method::SubmitFraudProof as u64,
+                &cbor::serialize(&proof, "test").unwrap(),
+            ),
+        );
+
+        // except the ones paying out the funds left in the subnet
+        runtime.expect_validate_caller_any();
+        runtime
+            .call::<Actor>(Method::FlushOutbox as u64, &RawBytes::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_inactive_grace_period() {
+        // only the first validator joins the validator set in delegated
+        // consensus, so the stake of other stakers doesn't activate the subnet.
+        let mut genesis = std_genesis();
+        genesis.consensus = ConsensusType::Delegated;
+        let mut params = std_construct_param();
+        params.consensus = ConsensusType::Delegated;
+        params.genesis = genesis.marshal_cbor().unwrap();
+        let mut runtime = construct_runtime_with_params(Address::new_id(1), params);
+
+        // a validator activates the subnet and a staker joins
+        let validator = Address::new_id(10);
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        runtime.set_balance(value.clone());
+        join_subnet(
+            &mut runtime,
+            validator,
+            value.clone(),
+            Some((ipc_gateway::Method::Register as u64, value.clone())),
+        )
+        .unwrap();
+
+        let staker = Address::new_id(30);
+        let staker_stake = value.clone();
+        runtime.set_balance(value.clone());
+        join_subnet(
+            &mut runtime,
+            staker,
+            staker_stake.clone(),
+            Some((ipc_gateway::Method::AddStake as u64, value.clone())),
+        )
+        .unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Active);
+        assert_eq!(st.validator_set.len(), 1);
+
+        // the validator leaves and the subnet becomes inactive
+        runtime.set_epoch(100);
+        leave_subnet(&mut runtime, validator, value.clone(), false).unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Inactive);
+        assert_eq!(st.inactive_since, Some(100));
+
+        // joining during the grace period re-activates the subnet
+        let validator = Address::new_id(20);
+        runtime.set_epoch(200);
+        runtime.set_balance(value.clone());
+        join_subnet(
+            &mut runtime,
+            validator,
+            value.clone(),
+            Some((ipc_gateway::Method::AddStake as u64, value.clone())),
+        )
+        .unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Active);
+        assert_eq!(st.inactive_since, None);
+
+        runtime.set_epoch(300);
+        leave_subnet(&mut runtime, validator, value.clone(), false).unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Inactive);
+
+        // stake is still locked so the subnet can't be killed yet
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, staker.clone());
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            runtime.call::<Actor>(Method::Kill as u64, &RawBytes::default()),
+        );
+
+        // once the grace period expires nobody can join anymore
+        runtime.set_epoch(300 + INACTIVE_GRACE_PERIOD);
+        runtime.set_balance(value.clone());
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            join_subnet(&mut runtime, Address::new_id(40), value.clone(), None),
+        );
+
+        // and anyone can terminate the subnet
+        runtime.set_balance(TokenAmount::zero());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, staker.clone());
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::kill(gateway_addr()),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(Method::Kill as u64, &RawBytes::default())
+            .unwrap();
+        runtime.verify();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Terminating);
+        assert_eq!(st.total_stake, staker_stake);
+
+        // the kill can't be finalized while stake is left
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            runtime.call::<Actor>(Method::FinalizeKill as u64, &RawBytes::default()),
+        );
+
+        // the remaining staker can still recover the stake returned by the gateway
+        leave_subnet(&mut runtime, staker, staker_stake.clone(), true).unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.total_stake, TokenAmount::zero());
+        assert!(st.outbox.is_empty());
+        assert_eq!(st.status, Status::Terminating);
+
+        runtime.expect_validate_caller_any();
+        runtime
+            .call::<Actor>(Method::FinalizeKill as u64, &RawBytes::default())
+            .unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Killed);
+
+        // status updates of any method terminate subnets whose
+        // grace period expired, e.g. validator removals
+        let receiver = Address::new_id(9999);
+        let validators = vec![
+            Address::new_id(10),
+            Address::new_id(20),
+            Address::new_id(30),
+        ];
+        let stake = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        let mut params = std_construct_param();
+        params.max_missed_windows = 1;
+        let mut runtime = setup_subnet_with_params(receiver, params, &validators);
+        let st: State = runtime.get_state();
+        runtime.set_epoch(3 * st.check_period);
+        runtime.set_balance(stake.clone());
+        join_subnet(
+            &mut runtime,
+            validators[0],
+            stake.clone(),
+            Some((ipc_gateway::Method::AddStake as u64, stake.clone())),
+        )
+        .unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Inactive);
+
+        runtime.set_epoch(3 * st.check_period + INACTIVE_GRACE_PERIOD);
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::ProposeRemoval,
+            RawBytes::serialize(validators[2]).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::release_stake(gateway_addr(), stake.clone()).unwrap(),
+            ExitCode::new(0),
+        );
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::kill(gateway_addr()),
+            ExitCode::new(0),
+        );
+        call_method(
+            &mut runtime,
+            validators[1],
+            Method::ProposeRemoval,
+            RawBytes::serialize(validators[2]).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        runtime.verify();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Terminating);
+        assert!(st.unregistered);
+
+        // inactive subnets only accept checkpoints when
+        // their validators hold enough stake
+        let mut genesis = std_genesis();
+        genesis.consensus = ConsensusType::Delegated;
+        let mut params = std_construct_param();
+        params.consensus = ConsensusType::Delegated;
+        params.genesis = genesis.marshal_cbor().unwrap();
+        let mut runtime = construct_runtime_with_params(receiver, params);
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        for (addr, method) in [
+            (Address::new_id(10), ipc_gateway::Method::Register),
+            (Address::new_id(30), ipc_gateway::Method::AddStake),
+        ] {
+            runtime.set_balance(value.clone());
+            join_subnet(
+                &mut runtime,
+                addr,
+                value.clone(),
+                Some((method as u64, value.clone())),
+            )
+            .unwrap();
+        }
+        leave_subnet(&mut runtime, Address::new_id(10), value.clone(), false).unwrap();
+
+        let validator = Address::new_id(20);
+        let half = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT / 2);
+        runtime.set_balance(half.clone());
+        join_subnet(
+            &mut runtime,
+            validator,
+            half.clone(),
+            Some((ipc_gateway::Method::AddStake as u64, half)),
+        )
+        .unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Inactive);
+        assert!(st.is_validator(&validator));
+        assert!(st.total_stake >= value);
+
+        let subnet = SubnetID::new(&st.parent_id, receiver);
+        let ch = signed_checkpoint(&subnet, st.check_period, st.genesis_cid);
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validator);
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            runtime.call::<Actor>(
+                Method::SubmitCheckpoint as u64,
+                &cbor::serialize(&ch, "test").unwrap(),
+            ),
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_cross_msgs() {
+        let receiver = Address::new_id(9999);
+        let validators = vec![
+            Address::new_id(10),
+            Address::new_id(20),
+            Address::new_id(30),
+        ];
+        let mut runtime = setup_subnet(receiver, &validators);
+
+        // value funded into the subnet can't be reported by anyone but the gateway
+        #[cfg(feature = "gateway-extensions")]
+        {
+            let value = TokenAmount::from_atto(100);
+            call_method(
+                &mut runtime,
+                validators[0],
+                Method::NotifyFund,
+                RawBytes::serialize(FundParams {
+                    value: value.clone(),
+                })
+                .unwrap(),
+                TokenAmount::zero(),
+                ExitCode::USR_FORBIDDEN,
+            );
+            call_method(
+                &mut runtime,
+                gateway_addr(),
+                Method::NotifyFund,
+                RawBytes::serialize(FundParams {
+                    value: TokenAmount::zero(),
+                })
+                .unwrap(),
+                TokenAmount::zero(),
+                ExitCode::USR_ILLEGAL_ARGUMENT,
+            );
+            call_method(
+                &mut runtime,
+                gateway_addr(),
+                Method::NotifyFund,
+                RawBytes::serialize(FundParams {
+                    value: value.clone(),
+                })
+                .unwrap(),
+                TokenAmount::zero(),
+                ExitCode::OK,
+            );
+            let st: State = runtime.get_state();
+            assert_eq!(st.circ_supply, value);
+        }
+        let st: State = runtime.get_state();
+
+        let root = SubnetID::from_str("/root").unwrap();
+        let subnet = SubnetID::new(&root, receiver);
+        let child = SubnetID::new(&subnet, Address::new_id(100));
+        let meta = |from: &SubnetID, to: &SubnetID, value: u64| CrossMsgMeta {
+            from: from.clone(),
+            to: to.clone(),
+            msgs_cid: st.genesis_cid.into(),
+            nonce: 0,
+            value: TokenAmount::from_atto(value),
+        };
+        let mut empty = meta(&subnet, &root, 10);
+        empty.msgs_cid = Default::default();
+
+        #[allow(unused_mut)]
+        let mut invalid = vec![
+            // duplicate metas
+            vec![meta(&subnet, &root, 10), meta(&subnet, &root, 10)],
+            // not bottom-up
+            vec![meta(&subnet, &child, 10)],
+            // not originated in the subnet
+            vec![meta(&root, &root, 10)],
+            // no messages
+            vec![empty],
+        ];
+        // releases more than what was funded
+        #[cfg(feature = "gateway-extensions")]
+        invalid.extend([
+            vec![meta(&subnet, &root, 150)],
+            vec![meta(&subnet, &root, 60), meta(&child, &root, 60)],
+        ]);
+        for cross_msgs in invalid {
+            let mut ch = signed_checkpoint(&subnet, 10, st.genesis_cid);
+            ch.data.cross_msgs = cross_msgs;
+            runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validators[0]);
+            runtime.expect_validate_caller_any();
+            expect_abort(
+                ExitCode::USR_ILLEGAL_STATE,
+                runtime.call::<Actor>(
+                    Method::SubmitCheckpoint as u64,
+                    &cbor::serialize(&ch, "test").unwrap(),
+                ),
+            );
+        }
+
+        // committing a valid checkpoint reduces the circulating supply
+        let mut ch = signed_checkpoint(&subnet, 10, st.genesis_cid);
+        ch.data.cross_msgs = vec![meta(&subnet, &root, 60), meta(&child, &root, 30)];
+        send_checkpoint(&mut runtime, validators[0], &ch, false).unwrap();
+        send_checkpoint(&mut runtime, validators[1], &ch, true).unwrap();
+        #[cfg(feature = "gateway-extensions")]
+        {
+            let st: State = runtime.get_state();
+            assert_eq!(st.circ_supply, TokenAmount::from_atto(10));
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_children() {
+        let receiver = Address::new_id(9999);
+        let validators = vec![
+            Address::new_id(10),
+            Address::new_id(20),
+            Address::new_id(30),
+        ];
+        let mut runtime = setup_subnet(receiver, &validators);
+        let st: State = runtime.get_state();
+
+        let root = SubnetID::from_str("/root").unwrap();
+        let subnet = SubnetID::new(&root, receiver);
+        let child_a = SubnetID::new(&subnet, Address::new_id(100));
+        let child_b = SubnetID::new(&subnet, Address::new_id(101));
+        let grandchild = SubnetID::new(&child_a, Address::new_id(102));
+        let check = |source: &SubnetID, epoch: ChainEpoch| {
+            TCid::from(Checkpoint::new(source.clone(), epoch).cid())
+        };
+        let child = |source: &SubnetID, checks: Vec<TCid<TLink<Checkpoint>>>| ChildCheck {
+            source: source.clone(),
+            checks,
+        };
+
+        let invalid = vec![
+            // not a direct child
+            vec![child(&grandchild, vec![check(&grandchild, 10)])],
+            vec![child(&root, vec![check(&root, 10)])],
+            // duplicate children
+            vec![
+                child(&child_a, vec![check(&child_a, 10)]),
+                child(&child_a, vec![check(&child_a, 20)]),
+            ],
+            // no checkpoints
+            vec![child(&child_b, vec![])],
+            // duplicate checkpoints
+            vec![child(
+                &child_b,
+                vec![check(&child_b, 10), check(&child_b, 10)],
+            )],
+        ];
+        for children in invalid {
+            let mut ch = signed_checkpoint(&subnet, 10, st.genesis_cid);
+            ch.data.children = children;
+            runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validators[0]);
+            runtime.expect_validate_caller_any();
+            expect_abort(
+                ExitCode::USR_ILLEGAL_ARGUMENT,
+                runtime.call::<Actor>(
+                    Method::SubmitCheckpoint as u64,
+                    &cbor::serialize(&ch, "test").unwrap(),
+                ),
+            );
+        }
+
+        let mut ch = signed_checkpoint(&subnet, 10, st.genesis_cid);
+        ch.data.children = vec![
+            child(&child_a, vec![check(&child_a, 10), check(&child_a, 20)]),
+            child(&child_b, vec![check(&child_b, 10)]),
+        ];
+        send_checkpoint(&mut runtime, validators[0], &ch, false).unwrap();
+        send_checkpoint(&mut runtime, validators[1], &ch, true).unwrap();
+    }
+
+    #[test]
+    fn test_fraud_proof() {
+        let receiver = Address::new_id(9999);
+        let validators = vec![
+            Address::new_id(10),
+            Address::new_id(20),
+            Address::new_id(30),
+        ];
+        let mut params = std_construct_param();
+        params.checkpoint_reward = TokenAmount::from_atto(10);
+        params.participation_policy = ParticipationPolicy {
+            period: 3,
+            ..Default::default()
+        };
+        let mut runtime = setup_subnet_with_params(receiver, params, &validators);
+
+        let funds = TokenAmount::from_atto(20);
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, Address::new_id(100));
+        runtime.set_value(funds.clone());
+        runtime.set_balance(funds.clone());
+        runtime.expect_validate_caller_any();
+        runtime
+            .call::<Actor>(Method::FundRewards as u64, &RawBytes::default())
+            .unwrap();
+        runtime.set_value(TokenAmount::zero());
+        let st: State = runtime.get_state();
+        let period = st.check_period;
+
+        let root = SubnetID::from_str("/root").unwrap();
+        let subnet = SubnetID::new(&root, receiver);
+
+        // commit a checkpoint skipping the first window
+        runtime.set_epoch(50);
+        let committed = signed_checkpoint(&subnet, 2 * period, st.genesis_cid);
+        send_checkpoint(&mut runtime, validators[0], &committed, false).unwrap();
+        send_checkpoint(&mut runtime, validators[1], &committed, true).unwrap();
+        let st: State = runtime.get_state();
+        let commit = st
+            .get_commit(runtime.store(), &(2 * period))
+            .unwrap()
+            .unwrap();
+        assert_eq!(commit.epoch, 50);
+        assert_eq!(commit.signers, vec![validators[0], validators[1]]);
+        assert_eq!(commit.skipped_windows, 1);
+        assert_eq!(st.missed_windows, 1);
+        assert_eq!(st.reward_pool, TokenAmount::from_atto(10));
+
+        // the first validator also signed a conflicting checkpoint
+        let conflicting = signed_checkpoint(&subnet, 2 * period, Cid::default());
+        let proof = FraudProof {
+            signer: validators[0],
+            first: committed.clone(),
+            second: committed.clone(),
+        };
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, Address::new_id(50));
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
+                Method::SubmitFraudProof as u64,
+                &cbor::serialize(&proof, "test").unwrap(),
+            ),
+        );
+
+        let proof = FraudProof {
+            signer: validators[0],
+            first: committed.clone(),
+            second: conflicting,
+        };
+        // both signers of the committed checkpoint are slashed if
+        // it can be reverted, and only the proven signer otherwise
+        #[cfg(feature = "gateway-extensions")]
+        let slashed = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT * 2);
+        #[cfg(not(feature = "gateway-extensions"))]
+        let slashed = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        runtime.set_epoch(60);
+        runtime.set_balance(slashed.clone() + &funds);
+        runtime.expect_validate_caller_any();
+        for ch in [&proof.first, &proof.second] {
+            runtime.expect_verify_signature(ExpectedVerifySig {
+                sig: Signature::new_secp256k1(vec![1, 2, 3, 4]),
+                signer: validators[0],
+                plaintext: ch.cid().to_bytes(),
+                result: Ok(()),
+            });
+        }
+        #[cfg(feature = "gateway-extensions")]
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::revert_child_checkpoint(gateway_addr(), &committed).unwrap(),
+            ExitCode::new(0),
+        );
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::release_stake(gateway_addr(), slashed.clone()).unwrap(),
+            ExitCode::new(0),
+        );
+        runtime.expect_send(
+            *BURNT_FUNDS_ACTOR_ADDR,
+            METHOD_SEND,
+            RawBytes::default(),
+            slashed.clone(),
+            RawBytes::default(),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(
+                Method::SubmitFraudProof as u64,
+                &cbor::serialize(&proof, "test").unwrap(),
+            )
+            .unwrap();
+        runtime.verify();
+
+        #[cfg(not(feature = "gateway-extensions"))]
+        {
+            // the checkpoint stays committed
+            let st: State = runtime.get_state();
+            assert!(st
+                .get_commit(runtime.store(), &(2 * period))
+                .unwrap()
+                .is_some());
+            assert_eq!(
+                st.get_stake(runtime.store(), &validators[0]).unwrap(),
+                Some(TokenAmount::zero())
+            );
+            assert_eq!(st.validator_set.len(), 2);
+            assert_eq!(
+                st.total_stake,
+                TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT * 2)
+            );
+            assert_eq!(st.reward_pool, TokenAmount::from_atto(10));
+
+            // fraud proofs are not accepted after the challenge window
+            let proof = FraudProof {
+                signer: validators[1],
+                first: committed.clone(),
+                second: signed_checkpoint(&subnet, 2 * period, Cid::default()),
+            };
+            runtime.set_epoch(50 + st.challenge_window + 1);
+            runtime.expect_validate_caller_any();
+            expect_abort(
+                ExitCode::USR_ILLEGAL_STATE,
+                runtime.call::<Actor>(
+                    Method::SubmitFraudProof as u64,
+                    &cbor::serialize(&proof, "test").unwrap(),
+                ),
+            );
+        }
+        #[cfg(feature = "gateway-extensions")]
+        {
+            // the checkpoint is reverted and its signers slashed
+            let st: State = runtime.get_state();
+            assert!(st
+                .get_checkpoint(runtime.store(), &(2 * period))
+                .unwrap()
+                .is_none());
+            assert!(st
+                .get_commit(runtime.store(), &(2 * period))
+                .unwrap()
+                .is_none());
+            for v in &validators[..2] {
+                assert_eq!(
+                    st.get_stake(runtime.store(), v).unwrap(),
+                    Some(TokenAmount::zero())
+                );
+            }
+            assert_eq!(st.validator_set.len(), 1);
+            assert_eq!(
+                st.total_stake,
+                TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT)
+            );
+
+            // and the rewards and liveness records of the commitment are rolled back
+            assert_eq!(st.reward_pool, funds);
+            assert_eq!(st.unclaimed_rewards, TokenAmount::zero());
+            for v in &validators[..2] {
+                assert_eq!(st.get_rewards(runtime.store(), v).unwrap(), None);
+            }
+            assert_eq!(st.missed_windows, 0);
+            assert_eq!(st.last_committed_epoch, 0);
+            assert_eq!(st.validator_set[0].missed_windows, 0);
+            assert!(st.validator_set[0].participation.is_empty());
+
+            // the window can be committed again, but fraud proofs
+            // are not accepted after the challenge window
+            runtime.set_epoch(70);
+            send_checkpoint(&mut runtime, validators[2], &committed, true).unwrap();
+            let st: State = runtime.get_state();
+            assert_eq!(st.missed_windows, 1);
+
+            let proof = FraudProof {
+                signer: validators[2],
+                first: committed.clone(),
+                second: signed_checkpoint(&subnet, 2 * period, Cid::default()),
+            };
+            runtime.set_epoch(70 + st.challenge_window + 1);
+            runtime.expect_validate_caller_any();
+            expect_abort(
+                ExitCode::USR_ILLEGAL_STATE,
+                runtime.call::<Actor>(
+                    Method::SubmitFraudProof as u64,
+                    &cbor::serialize(&proof, "test").unwrap(),
+                ),
+            );
+        }
+    }
+
+    #[test]
+    fn test_missed_windows() {
+        let receiver = Address::new_id(9999);
+        let validators = vec![
+            Address::new_id(10),
+            Address::new_id(20),
+            Address::new_id(30),
+        ];
+        let mut params = std_construct_param();
+        params.max_missed_windows = 3;
+        let mut runtime = setup_subnet_with_params(receiver, params, &validators);
+        let st: State = runtime.get_state();
+        let period = st.check_period;
+
+        let root = SubnetID::from_str("/root").unwrap();
+        let subnet = SubnetID::new(&root, receiver);
+
+        // the deadlines of the first two windows have passed
+        runtime.set_epoch(3 * period + 5);
+        let stats = liveness_stats(&mut runtime);
+        assert_eq!(stats.consecutive_missed_windows, 2);
+        assert_eq!(stats.missed_windows, 0);
+
+        // committing the third window accounts for the missed ones
+        let ch = signed_checkpoint(&subnet, 3 * period, st.genesis_cid);
+        send_checkpoint(&mut runtime, validators[0], &ch, false).unwrap();
+        send_checkpoint(&mut runtime, validators[1], &ch, true).unwrap();
+        let stats = liveness_stats(&mut runtime);
+        assert_eq!(stats.last_committed_epoch, 3 * period);
+        assert_eq!(stats.consecutive_missed_windows, 0);
+        assert_eq!(stats.missed_windows, 2);
+        for v in &stats.validators {
+            assert_eq!(v.missed_windows, 2);
+        }
+
+        // after missing too many windows the subnet becomes inactive
+        runtime.set_epoch(7 * period + 5);
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        let new_validator = Address::new_id(40);
+        runtime.set_balance(value.clone());
+        join_subnet(
+            &mut runtime,
+            new_validator,
+            value.clone(),
+            Some((ipc_gateway::Method::AddStake as u64, value.clone())),
+        )
+        .unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Inactive);
+
+        // but it recovers by committing checkpoints
+        let ch = signed_checkpoint(&subnet, 7 * period, ch.cid());
+        send_checkpoint(&mut runtime, validators[0], &ch, false).unwrap();
+        send_checkpoint(&mut runtime, validators[1], &ch, false).unwrap();
+        send_checkpoint(&mut runtime, validators[2], &ch, true).unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.status, Status::Active);
+
+        let stats = liveness_stats(&mut runtime);
+        assert_eq!(stats.missed_windows, 5);
+        assert_eq!(stats.consecutive_missed_windows, 0);
+        for v in &stats.validators {
+            let expected = if v.addr == new_validator { 3 } else { 5 };
+            assert_eq!(v.missed_windows, expected);
+        }
+    }
+
+    #[test]
+    fn test_participation_penalties() {
+        let receiver = Address::new_id(9999);
+        let validators = vec![
+            Address::new_id(10),
+            Address::new_id(20),
+            Address::new_id(30),
+        ];
+        let root = SubnetID::from_str("/root").unwrap();
+        let subnet = SubnetID::new(&root, receiver);
+        let stake = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+
+        // percentages can't be greater than 100
+        for (min_participation, burn_percentage) in [(101, 10), (100, 101)] {
+            let mut params = std_construct_param();
+            params.participation_policy = ParticipationPolicy {
+                period: 2,
+                min_participation,
+                penalty: LivenessPenalty::Burn,
+                burn_percentage,
+            };
+            let mut runtime = MockRuntime::new(receiver, *INIT_ACTOR_ADDR);
+            runtime.expect_validate_caller_addr(vec![*INIT_ACTOR_ADDR]);
+            expect_abort(
+                ExitCode::USR_ILLEGAL_ARGUMENT,
+                runtime.call::<Actor>(
+                    Method::Constructor as u64,
+                    &cbor::serialize(&params, "test").unwrap(),
+                ),
+            );
+        }
+
+        for penalty in [LivenessPenalty::Eject, LivenessPenalty::Burn] {
+            let mut params = std_construct_param();
+            params.participation_policy = ParticipationPolicy {
+                period: 2,
+                min_participation: 100,
+                penalty,
+                burn_percentage: 10,
+            };
+            let mut runtime = setup_subnet_with_params(receiver, params, &validators);
+            let st: State = runtime.get_state();
+
+            // the third validator doesn't sign the first window
+            let ch = signed_checkpoint(&subnet, st.check_period, st.genesis_cid);
+            send_checkpoint(&mut runtime, validators[0], &ch, false).unwrap();
+            send_checkpoint(&mut runtime, validators[1], &ch, true).unwrap();
+            let st: State = runtime.get_state();
+            assert_eq!(st.validator_set.len(), 3);
+            assert_eq!(st.validator_set[0].participation, vec![true]);
+            assert_eq!(st.validator_set[2].participation, vec![false]);
+
+            // nor the second one, so it is penalized
+            let ch = signed_checkpoint(&subnet, 2 * st.check_period, ch.cid());
+            send_checkpoint(&mut runtime, validators[0], &ch, false).unwrap();
+            let burnt = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT / 10);
+            let penalized = match penalty {
+                LivenessPenalty::Burn => (burnt.clone(), burnt.clone()),
+                _ => (stake.clone(), TokenAmount::zero()),
+            };
+            send_checkpoint_with_penalties(&mut runtime, validators[1], &ch, true, Some(penalized))
+                .unwrap();
+            runtime.verify();
+
+            let st: State = runtime.get_state();
+            assert_eq!(st.validator_set.len(), 2);
+            assert!(!st.is_validator(&validators[2]));
+            let remaining = st.get_stake(runtime.store(), &validators[2]).unwrap();
+            match penalty {
+                LivenessPenalty::Burn => {
+                    assert_eq!(remaining, Some(stake.clone() - &burnt));
+                    assert_eq!(
+                        st.total_stake,
+                        TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT * 3) - &burnt
+                    );
+                }
+                _ => {
+                    // the stake of ejected validators goes through unbonding
+                    // and they need to wait before joining again
+                    assert_eq!(remaining, Some(TokenAmount::zero()));
+                    assert_eq!(st.total_stake, stake.clone() * 2);
+                    runtime.set_balance(stake.clone());
+                    expect_abort(
+                        ExitCode::USR_FORBIDDEN,
+                        join_subnet(&mut runtime, validators[2], stake.clone(), None),
+                    );
+
+                    runtime.set_epoch(REJOIN_COOLDOWN);
+                    join_subnet(
+                        &mut runtime,
+                        validators[2],
+                        stake.clone(),
+                        Some((ipc_gateway::Method::AddStake as u64, stake.clone())),
+                    )
+                    .unwrap();
+                    assert!(runtime.get_state::<State>().is_validator(&validators[2]));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_rewards() {
+        let receiver = Address::new_id(9999);
+        let validators = vec![
+            Address::new_id(10),
+            Address::new_id(20),
+            Address::new_id(30),
+        ];
+        let root = SubnetID::from_str("/root").unwrap();
+        let subnet = SubnetID::new(&root, receiver);
+
+        let mut params = std_construct_param();
+        params.checkpoint_reward = TokenAmount::from_atto(10);
+        let mut runtime = setup_subnet_with_params(receiver, params, &validators);
+
+        // funding the pool requires some value
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, Address::new_id(100));
+        runtime.set_value(TokenAmount::zero());
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(Method::FundRewards as u64, &RawBytes::default()),
+        );
+
+        let funds = TokenAmount::from_atto(15);
+        runtime.set_value(funds.clone());
+        runtime.set_balance(funds.clone());
+        runtime.expect_validate_caller_any();
+        runtime
+            .call::<Actor>(Method::FundRewards as u64, &RawBytes::default())
+            .unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.reward_pool, funds);
+
+        // the reward is shared among the signers of the checkpoint
+        let ch = signed_checkpoint(&subnet, st.check_period, st.genesis_cid);
+        send_checkpoint(&mut runtime, validators[0], &ch, false).unwrap();
+        send_checkpoint(&mut runtime, validators[1], &ch, true).unwrap();
+        let st: State = runtime.get_state();
+        let share = TokenAmount::from_atto(5);
+        assert_eq!(st.reward_pool, TokenAmount::from_atto(5));
+        assert_eq!(st.unclaimed_rewards, TokenAmount::from_atto(10));
+        for v in &validators[..2] {
+            assert_eq!(
+                st.get_rewards(runtime.store(), v).unwrap(),
+                Some(share.clone())
+            );
+        }
+        assert_eq!(
+            st.get_rewards(runtime.store(), &validators[2]).unwrap(),
+            None
+        );
+
+        // the reward is capped by the funds left in the pool
+        let ch = signed_checkpoint(&subnet, 2 * st.check_period, ch.cid());
+        send_checkpoint(&mut runtime, validators[1], &ch, false).unwrap();
+        send_checkpoint(&mut runtime, validators[2], &ch, true).unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.reward_pool, TokenAmount::from_atto(1));
+        assert_eq!(
+            st.get_rewards(runtime.store(), &validators[1]).unwrap(),
+            Some(TokenAmount::from_atto(7))
+        );
+        assert_eq!(
+            st.get_rewards(runtime.store(), &validators[2]).unwrap(),
+            Some(TokenAmount::from_atto(2))
+        );
+
+        // claim rewards
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validators[0]);
+        runtime.set_value(TokenAmount::zero());
+        runtime.expect_validate_caller_any();
+        runtime.expect_send(
+            validators[0],
+            METHOD_SEND,
+            RawBytes::default(),
+            share.clone(),
+            RawBytes::default(),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(Method::ClaimRewards as u64, &RawBytes::default())
+            .unwrap();
+        runtime.verify();
+        let st: State = runtime.get_state();
+        assert_eq!(
+            st.get_rewards(runtime.store(), &validators[0]).unwrap(),
+            None
+        );
+        assert_eq!(st.unclaimed_rewards, TokenAmount::from_atto(9));
+
+        // nothing left to claim
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            runtime.call::<Actor>(Method::ClaimRewards as u64, &RawBytes::default()),
+        );
+    }
+
+    #[test]
+    fn test_leaving_penalty() {
+        let receiver = Address::new_id(9999);
+        let validators = vec![
+            Address::new_id(10),
+            Address::new_id(20),
+            Address::new_id(30),
+        ];
+        let stake = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        let penalty = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT / 4);
+
+        // invalid penalties are rejected
+        let mut params = std_construct_param();
+        params.leaving_penalty = LeavingPenalty {
+            numerator: 3,
+            denominator: 2,
+            destination: PenaltyDestination::Burn,
+        };
+        let mut runtime = MockRuntime::new(receiver, *INIT_ACTOR_ADDR);
+        runtime.expect_validate_caller_addr(vec![*INIT_ACTOR_ADDR]);
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
+                Method::Constructor as u64,
+                &cbor::serialize(&params, "test").unwrap(),
+            ),
+        );
+
+        // the penalty is burnt
+        params.leaving_penalty = LeavingPenalty {
+            numerator: 1,
+            denominator: 4,
+            destination: PenaltyDestination::Burn,
+        };
+        let mut runtime = setup_subnet_with_params(receiver, params.clone(), &validators);
+        leave_subnet_with_burn(
+            &mut runtime,
+            validators[2],
+            stake.clone(),
+            false,
+            Some(penalty.clone()),
+        )
+        .unwrap();
+        let st: State = runtime.get_state();
+        assert_eq!(st.total_stake, stake.clone() + &stake);
+        assert_eq!(st.validator_set.len(), 2);
+
+        // the penalty is shared among the remaining validators
+        params.leaving_penalty.destination = PenaltyDestination::Redistribute;
+        let mut runtime = setup_subnet_with_params(receiver, params, &validators);
+        leave_subnet(&mut runtime, validators[2], stake.clone() - &penalty, false).unwrap();
+        let st: State = runtime.get_state();
+        let mut sum = TokenAmount::zero();
+        for v in &validators[..2] {
+            let s = st.get_stake(runtime.store(), v).unwrap().unwrap();
+            assert_eq!(
+                s,
+                stake.clone() + TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT / 8)
+            );
+            sum += s;
+        }
+        assert_eq!(st.total_stake, sum);
+    }
+
+    #[test]
+    fn test_delegation() {
+        let receiver = Address::new_id(9999);
+        let validators = vec![
+            Address::new_id(10),
+            Address::new_id(20),
+            Address::new_id(30),
+        ];
+        let delegator = Address::new_id(100);
+        let root = SubnetID::from_str("/root").unwrap();
+        let subnet = SubnetID::new(&root, receiver);
+        let stake = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        let mut runtime = setup_subnet(receiver, &validators);
+
+        // stake can only be delegated to validators
+        let params = DelegateParams {
+            validator: delegator,
+            amount: stake.clone(),
+        };
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, delegator);
+        runtime.set_value(stake.clone());
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
+                Method::Delegate as u64,
+                &RawBytes::serialize(params).unwrap(),
+            ),
+        );
+
+        // the value sent needs to match the amount delegated
+        let params = DelegateParams {
+            validator: validators[2],
+            amount: stake.clone(),
+        };
+        runtime.set_value(TokenAmount::from_atto(1));
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
+                Method::Delegate as u64,
+                &RawBytes::serialize(params.clone()).unwrap(),
+            ),
+        );
+
+        runtime.set_value(stake.clone());
+        runtime.set_balance(stake.clone());
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::add_stake(gateway_addr(), stake.clone()),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(
+                Method::Delegate as u64,
+                &RawBytes::serialize(params).unwrap(),
+            )
+            .unwrap();
+        runtime.verify();
+        let st: State = runtime.get_state();
+        assert_eq!(st.total_delegated, stake);
+        assert_eq!(
+            st.voting_power(runtime.store(), &validators[2]).unwrap(),
+            stake.clone() + &stake
+        );
+
+        // the votes of the other two validators are no longer a majority
+        let ch = signed_checkpoint(&subnet, st.check_period, st.genesis_cid);
+        send_checkpoint(&mut runtime, validators[0], &ch, false).unwrap();
+        send_checkpoint(&mut runtime, validators[1], &ch, false).unwrap();
+        send_checkpoint(&mut runtime, validators[2], &ch, true).unwrap();
+
+        // undelegate
+        let half = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT / 2);
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, delegator);
+        runtime.set_value(TokenAmount::zero());
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+            runtime.call::<Actor>(
+                Method::Undelegate as u64,
+                &RawBytes::serialize(UndelegateParams {
+                    validator: validators[2],
+                    amount: stake.clone() + &stake,
+                })
+                .unwrap(),
+            ),
+        );
+
+        runtime.set_epoch(100);
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::release_stake(gateway_addr(), half.clone()).unwrap(),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(
+                Method::Undelegate as u64,
+                &RawBytes::serialize(UndelegateParams {
+                    validator: validators[2],
+                    amount: half.clone(),
+                })
+                .unwrap(),
+            )
+            .unwrap();
+        runtime.verify();
+        let st: State = runtime.get_state();
+        assert_eq!(st.total_delegated, half);
+
+        // the stake can't be withdrawn until the unbonding period is over
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            runtime.call::<Actor>(Method::WithdrawUnbonded as u64, &RawBytes::default()),
+        );
+
+        runtime.set_epoch(100 + UNBONDING_PERIOD);
+        runtime.set_balance(half.clone());
         runtime.expect_validate_caller_any();
         runtime.expect_send(
-            Address::new_id(IPC_GATEWAY_ADDR),
-            ipc_gateway::Method::ReleaseStake as u64,
-            RawBytes::serialize(FundParams {
-                value: value.clone(),
-            })
-            .unwrap(),
-            TokenAmount::zero(),
+            delegator,
+            METHOD_SEND,
+            RawBytes::default(),
+            half,
             RawBytes::default(),
             ExitCode::new(0),
         );
         runtime
-            .call::<Actor>(Method::Leave as u64, &RawBytes::default())
+            .call::<Actor>(Method::WithdrawUnbonded as u64, &RawBytes::default())
             .unwrap();
+        runtime.verify();
+
+        // the stake delegated to a validator leaving the set is undelegated
+        let remaining = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT / 2);
+        leave_subnet(
+            &mut runtime,
+            validators[2],
+            stake.clone() + &remaining,
+            false,
+        )
+        .unwrap();
         let st: State = runtime.get_state();
-        assert_eq!(st.validator_set.len(), 0);
-        assert_eq!(st.status, Status::Inactive);
-        assert_eq!(st.total_stake, total_stake);
-        assert_eq!(
-            st.get_stake(runtime.store(), &caller).unwrap().unwrap(),
-            TokenAmount::zero()
-        );
+        assert_eq!(st.total_delegated, TokenAmount::zero());
+        assert!(st
+            .get_delegations(runtime.store(), &validators[2])
+            .unwrap()
+            .is_empty());
 
-        // to kill the subnet
-        runtime.set_value(value.clone());
-        runtime.set_caller(Cid::default(), caller.clone());
+        runtime.set_epoch(100 + 2 * UNBONDING_PERIOD);
+        runtime.set_balance(remaining.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, delegator);
         runtime.expect_validate_caller_any();
         runtime.expect_send(
-            Address::new_id(IPC_GATEWAY_ADDR),
-            ipc_gateway::Method::Kill as u64,
+            delegator,
+            METHOD_SEND,
             RawBytes::default(),
-            TokenAmount::zero(),
+            remaining,
             RawBytes::default(),
             ExitCode::new(0),
         );
         runtime
-            .call::<Actor>(Method::Kill as u64, &RawBytes::default())
+            .call::<Actor>(Method::WithdrawUnbonded as u64, &RawBytes::default())
+            .unwrap();
+        runtime.verify();
+    }
+
+    #[test]
+    fn test_commission() {
+        let receiver = Address::new_id(9999);
+        let validators = vec![
+            Address::new_id(10),
+            Address::new_id(20),
+            Address::new_id(30),
+        ];
+        let delegator = Address::new_id(100);
+        let root = SubnetID::from_str("/root").unwrap();
+        let subnet = SubnetID::new(&root, receiver);
+        let stake = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+
+        let mut params = std_construct_param();
+        params.checkpoint_reward = TokenAmount::from_atto(100);
+        let mut runtime = setup_subnet_with_params(receiver, params, &validators);
+
+        // increases at genesis count toward the limit too
+        call_method(
+            &mut runtime,
+            validators[1],
+            Method::SetCommission,
+            RawBytes::serialize(SetCommissionParams { commission: 5 }).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        call_method(
+            &mut runtime,
+            validators[1],
+            Method::SetCommission,
+            RawBytes::serialize(SetCommissionParams { commission: 10 }).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+        call_method(
+            &mut runtime,
+            validators[1],
+            Method::SetCommission,
+            RawBytes::serialize(SetCommissionParams { commission: 0 }).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+
+        // only validators have a commission rate
+        runtime.set_epoch(10);
+        call_method(
+            &mut runtime,
+            delegator,
+            Method::SetCommission,
+            RawBytes::serialize(SetCommissionParams { commission: 5 }).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_FORBIDDEN,
+        );
+        // increases are limited
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::SetCommission,
+            RawBytes::serialize(SetCommissionParams { commission: 20 }).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_ILLEGAL_ARGUMENT,
+        );
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::SetCommission,
+            RawBytes::serialize(SetCommissionParams { commission: 5 }).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        runtime.set_epoch(20);
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::SetCommission,
+            RawBytes::serialize(SetCommissionParams { commission: 10 }).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::USR_ILLEGAL_STATE,
+        );
+        // decreases are not
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::SetCommission,
+            RawBytes::serialize(SetCommissionParams { commission: 0 }).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        runtime.set_epoch(10 + COMMISSION_UPDATE_PERIOD);
+        call_method(
+            &mut runtime,
+            validators[0],
+            Method::SetCommission,
+            RawBytes::serialize(SetCommissionParams { commission: 5 }).unwrap(),
+            TokenAmount::zero(),
+            ExitCode::OK,
+        );
+        let st: State = runtime.get_state();
+        assert_eq!(st.validator_set[0].commission, 5);
+
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, delegator);
+        runtime.set_value(stake.clone());
+        runtime.set_balance(stake.clone());
+        runtime.expect_validate_caller_any();
+        expect_gateway_send(
+            &mut runtime,
+            ext::gateway::add_stake(gateway_addr(), stake.clone()),
+            ExitCode::new(0),
+        );
+        runtime
+            .call::<Actor>(
+                Method::Delegate as u64,
+                &RawBytes::serialize(DelegateParams {
+                    validator: validators[0],
+                    amount: stake.clone(),
+                })
+                .unwrap(),
+            )
+            .unwrap();
+
+        let funds = TokenAmount::from_atto(100);
+        runtime.set_value(funds.clone());
+        runtime.set_balance(funds);
+        runtime.expect_validate_caller_any();
+        runtime
+            .call::<Actor>(Method::FundRewards as u64, &RawBytes::default())
             .unwrap();
+
+        // the validator takes its commission before sharing with the delegator
         let st: State = runtime.get_state();
-        assert_eq!(st.total_stake, TokenAmount::zero());
-        assert_eq!(st.status, Status::Killed);
+        let ch = signed_checkpoint(&subnet, st.check_period, st.genesis_cid);
+        send_checkpoint(&mut runtime, validators[0], &ch, false).unwrap();
+        send_checkpoint(&mut runtime, validators[1], &ch, true).unwrap();
+        let st: State = runtime.get_state();
+        for (addr, reward) in [(validators[0], 27), (delegator, 22), (validators[1], 25)] {
+            assert_eq!(
+                st.get_rewards(runtime.store(), &addr).unwrap(),
+                Some(TokenAmount::from_atto(reward))
+            );
+        }
+        assert_eq!(st.reward_pool, TokenAmount::from_atto(26));
     }
 
     #[test]
@@ -440,6 +3054,7 @@ mod test {
         let validator = Address::new_id(100);
         let params = JoinParams {
             validator_net_addr: validator.to_string(),
+            signing_key: None,
         };
 
         // first miner joins the subnet
@@ -449,24 +3064,25 @@ mod test {
         for caller in &miners {
             runtime.set_value(value.clone());
             runtime.set_balance(TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT));
-            runtime.set_caller(Cid::default(), caller.clone());
+            runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller.clone());
             runtime.expect_validate_caller_any();
+            expect_resolve_key(&mut runtime, caller.clone());
             if i == 0 {
-                runtime.expect_send(
-                    Address::new_id(IPC_GATEWAY_ADDR),
-                    ipc_gateway::Method::Register as u64,
-                    RawBytes::default(),
-                    TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
-                    RawBytes::default(),
+                expect_gateway_send(
+                    &mut runtime,
+                    ext::gateway::register(
+                        gateway_addr(),
+                        TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
+                    ),
                     ExitCode::new(0),
                 );
             } else {
-                runtime.expect_send(
-                    Address::new_id(IPC_GATEWAY_ADDR),
-                    ipc_gateway::Method::AddStake as u64,
-                    RawBytes::default(),
-                    TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
-                    RawBytes::default(),
+                expect_gateway_send(
+                    &mut runtime,
+                    ext::gateway::add_stake(
+                        gateway_addr(),
+                        TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT),
+                    ),
                     ExitCode::new(0),
                 );
             }
@@ -490,7 +3106,21 @@ mod test {
         let root_subnet = SubnetID::from_str("/root").unwrap();
         let subnet = SubnetID::new(&root_subnet, test_actor_address);
         let epoch = 10;
+
+        // The first checkpoint has to descend from the genesis.
+        let orphan = Checkpoint::new(subnet.clone(), epoch);
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, miners[0].clone());
+        runtime.expect_validate_caller_any();
+        expect_abort(
+            ExitCode::USR_ILLEGAL_STATE,
+            runtime.call::<Actor>(
+                Method::SubmitCheckpoint as u64,
+                &cbor::serialize(&orphan, "test").unwrap(),
+            ),
+        );
+
         let mut checkpoint_0 = Checkpoint::new(subnet.clone(), epoch);
+        checkpoint_0.data.prev_check = TCid::from(st.genesis_cid);
         checkpoint_0.set_signature(
             RawBytes::serialize(Signature::new_secp256k1(vec![1, 2, 3, 4]))
                 .unwrap()
@@ -498,10 +3128,11 @@ mod test {
                 .to_vec(),
         );
 
-        // Only validators should be entitled to submit checkpoints.
+        // Only validators or their signing keys should be entitled to submit checkpoints.
         let non_miner = Address::new_id(40);
-        runtime.set_caller(Cid::default(), non_miner.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, non_miner.clone());
         runtime.expect_validate_caller_any();
+        expect_resolve_key(&mut runtime, non_miner);
         expect_abort(
             ExitCode::USR_ILLEGAL_STATE,
             runtime.call::<Actor>(
@@ -535,7 +3166,7 @@ mod test {
 
         // Trying to submit an already committed checkpoint should fail
         let sender2 = miners.get(2).cloned().unwrap();
-        runtime.set_caller(Cid::default(), sender2.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, sender2.clone());
         runtime.expect_validate_caller_any();
         expect_abort(
             ExitCode::USR_ILLEGAL_STATE,
@@ -549,7 +3180,7 @@ mod test {
         let prev_cid = checkpoint_0.cid();
         let mut checkpoint_1 = Checkpoint::new(subnet.clone(), epoch + 1);
         checkpoint_1.data.prev_check = TCid::from(prev_cid.clone());
-        runtime.set_caller(Cid::default(), sender.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, sender.clone());
         runtime.expect_validate_caller_any();
         expect_abort(
             ExitCode::USR_ILLEGAL_STATE,
@@ -563,7 +3194,7 @@ mod test {
         let epoch = 20;
         let mut checkpoint_3 = Checkpoint::new(subnet.clone(), epoch);
         checkpoint_3.data.prev_check = TCid::from(Cid::default());
-        runtime.set_caller(Cid::default(), sender.clone());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, sender.clone());
         runtime.expect_validate_caller_any();
         expect_abort(
             ExitCode::USR_ILLEGAL_STATE,
@@ -593,21 +3224,327 @@ mod test {
         assert_eq!(votes.validators, vec![sender.clone()]);
     }
 
-    fn send_checkpoint(
+    /// Constructs a subnet activated by the given validators.
+    fn setup_subnet(receiver: Address, validators: &[Address]) -> MockRuntime {
+        setup_subnet_with_params(receiver, std_construct_param(), validators)
+    }
+
+    fn setup_subnet_with_params(
+        receiver: Address,
+        params: ConstructParams,
+        validators: &[Address],
+    ) -> MockRuntime {
+        let mut runtime = construct_runtime_with_params(receiver, params);
+        let value = TokenAmount::from_atto(MIN_COLLATERAL_AMOUNT);
+        for (i, v) in validators.iter().enumerate() {
+            let method = if i == 0 {
+                ipc_gateway::Method::Register
+            } else {
+                ipc_gateway::Method::AddStake
+            };
+            runtime.set_balance(value.clone());
+            join_subnet(
+                &mut runtime,
+                *v,
+                value.clone(),
+                Some((method as u64, value.clone())),
+            )
+            .unwrap();
+        }
+        runtime
+    }
+
+    fn signed_checkpoint(subnet: &SubnetID, epoch: ChainEpoch, prev: Cid) -> Checkpoint {
+        let mut ch = Checkpoint::new(subnet.clone(), epoch);
+        ch.data.prev_check = TCid::from(prev);
+        ch.set_signature(
+            RawBytes::serialize(Signature::new_secp256k1(vec![1, 2, 3, 4]))
+                .unwrap()
+                .bytes()
+                .to_vec(),
+        );
+        ch
+    }
+
+    /// Expects the query of the collateral of the subnet, with the gateway
+    /// reporting `collateral`.
+    #[cfg(feature = "gateway-extensions")]
+    fn expect_get_collateral(
         runtime: &mut MockRuntime,
-        sender: Address,
-        checkpoint: &Checkpoint,
-        is_commit: bool,
-    ) -> Result<RawBytes, ActorError> {
-        runtime.set_caller(Cid::default(), sender.clone());
+        subnet: &SubnetID,
+        collateral: TokenAmount,
+    ) {
+        runtime.expect_send(
+            gateway_addr(),
+            ext::gateway::GET_COLLATERAL_METHOD,
+            RawBytes::serialize(subnet).unwrap(),
+            TokenAmount::zero(),
+            RawBytes::serialize(&collateral).unwrap(),
+            ExitCode::new(0),
+        );
+    }
+
+    fn sync_stake(
+        runtime: &mut MockRuntime,
+        correction: Option<CrossActorPayload>,
+    ) -> CollateralStatus {
+        runtime.set_value(TokenAmount::zero());
+        runtime.expect_validate_caller_any();
+        if let Some(p) = correction {
+            runtime.set_balance(p.value.clone());
+            expect_gateway_send(runtime, p, ExitCode::new(0));
+        }
+        let status = runtime
+            .call::<Actor>(Method::SyncStake as u64, &RawBytes::default())
+            .unwrap()
+            .deserialize()
+            .unwrap();
+        runtime.verify();
+        status
+    }
+
+    /// Calls `method` of the actor from `caller` with `params` and `value`,
+    /// expecting it to exit with `code`.
+    fn call_method(
+        runtime: &mut MockRuntime,
+        caller: Address,
+        method: Method,
+        params: RawBytes,
+        value: TokenAmount,
+        code: ExitCode,
+    ) {
+        let method = method as u64;
+        let gateway_methods = [
+            Method::CollectFees as u64,
+            #[cfg(feature = "gateway-extensions")]
+            Method::NotifyFund as u64,
+        ];
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller);
+        runtime.set_value(value);
+        if gateway_methods.contains(&method) {
+            runtime.expect_validate_caller_addr(vec![gateway_addr()]);
+        } else {
+            runtime.expect_validate_caller_any();
+        }
+        let res = runtime.call::<Actor>(method, &params);
+        if code == ExitCode::OK {
+            res.unwrap();
+        } else {
+            expect_abort(code, res);
+        }
+        runtime.verify();
+    }
+
+    fn collateral_status(runtime: &mut MockRuntime) -> CollateralStatus {
+        runtime.expect_validate_caller_any();
+        runtime
+            .call::<Actor>(Method::CollateralStatus as u64, &RawBytes::default())
+            .unwrap()
+            .deserialize()
+            .unwrap()
+    }
+
+    fn allow_list(runtime: &mut MockRuntime) -> Vec<Address> {
+        runtime.expect_validate_caller_any();
+        runtime
+            .call::<Actor>(Method::AllowList as u64, &RawBytes::default())
+            .unwrap()
+            .deserialize()
+            .unwrap()
+    }
+
+    fn liveness_stats(runtime: &mut MockRuntime) -> LivenessStats {
+        runtime.expect_validate_caller_any();
+        let ret = runtime
+            .call::<Actor>(Method::LivenessStats as u64, &RawBytes::default())
+            .unwrap();
+        let ret: Option<RawBytes> = ret.deserialize().unwrap();
+        ret.unwrap().deserialize().unwrap()
+    }
+
+    fn gateway_addr() -> Address {
+        Address::new_id(IPC_GATEWAY_ADDR)
+    }
+
+    /// Expects a message to the gateway built with its typed client.
+    fn expect_gateway_send(runtime: &mut MockRuntime, p: CrossActorPayload, code: ExitCode) {
+        runtime.expect_send(p.to, p.method, p.params, p.value, RawBytes::default(), code);
+    }
+
+    fn expect_resolve_key(runtime: &mut MockRuntime, addr: Address) {
+        expect_pubkey(runtime, addr, addr);
+    }
+
+    /// Returns the params to register `key` as the signing key of
+    /// `validator`, expecting the verification of its proof of possession.
+    fn key_params(
+        runtime: &mut MockRuntime,
+        validator: Address,
+        key: Address,
+        valid: bool,
+    ) -> SigningKeyParams {
+        let proof = Signature::new_secp256k1(vec![5, 6, 7, 8]);
+        runtime.expect_verify_signature(ExpectedVerifySig {
+            sig: proof.clone(),
+            signer: key,
+            plaintext: validator.to_bytes(),
+            result: if valid {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!("invalid signature"))
+            },
+        });
+        SigningKeyParams { key, proof }
+    }
+
+    fn expect_pubkey(runtime: &mut MockRuntime, addr: Address, key: Address) {
         runtime.expect_send(
-            sender.clone(),
+            addr,
             ext::account::PUBKEY_ADDRESS_METHOD as u64,
             RawBytes::default(),
             TokenAmount::zero(),
-            cbor::serialize(&sender.clone(), "test").unwrap(),
+            cbor::serialize(&key, "test").unwrap(),
+            ExitCode::new(0),
+        );
+    }
+
+    fn join_subnet(
+        runtime: &mut MockRuntime,
+        caller: Address,
+        value: TokenAmount,
+        expected_send: Option<(u64, TokenAmount)>,
+    ) -> Result<RawBytes, ActorError> {
+        let params = JoinParams {
+            validator_net_addr: caller.to_string(),
+            signing_key: None,
+        };
+        let st: State = runtime.get_state();
+        let new_validator = value != TokenAmount::zero() && !st.is_validator(&caller);
+        runtime.set_value(value);
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller);
+        runtime.expect_validate_caller_any();
+        if new_validator {
+            expect_resolve_key(runtime, caller);
+        }
+        if let Some((method, amount)) = expected_send {
+            runtime.expect_send(
+                gateway_addr(),
+                method,
+                RawBytes::default(),
+                amount,
+                RawBytes::default(),
+                ExitCode::new(0),
+            );
+        }
+        let res = runtime.call::<Actor>(
+            Method::Join as u64,
+            &cbor::serialize(&params, "test").unwrap(),
+        );
+        runtime.verify();
+        res
+    }
+
+    fn leave_subnet(
+        runtime: &mut MockRuntime,
+        caller: Address,
+        stake: TokenAmount,
+        terminating: bool,
+    ) -> Result<RawBytes, ActorError> {
+        leave_subnet_with_burn(runtime, caller, stake, terminating, None)
+    }
+
+    fn leave_subnet_with_burn(
+        runtime: &mut MockRuntime,
+        caller: Address,
+        released: TokenAmount,
+        terminating: bool,
+        burnt: Option<TokenAmount>,
+    ) -> Result<RawBytes, ActorError> {
+        runtime.set_value(TokenAmount::zero());
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, caller);
+        runtime.expect_validate_caller_any();
+        if terminating {
+            runtime.set_balance(released.clone());
+            runtime.expect_send(
+                caller,
+                METHOD_SEND,
+                RawBytes::default(),
+                released,
+                RawBytes::default(),
+                ExitCode::new(0),
+            );
+        } else {
+            expect_gateway_send(
+                runtime,
+                ext::gateway::release_stake(gateway_addr(), released).unwrap(),
+                ExitCode::new(0),
+            );
+        }
+        if let Some(amount) = burnt {
+            runtime.set_balance(amount.clone());
+            runtime.expect_send(
+                *BURNT_FUNDS_ACTOR_ADDR,
+                METHOD_SEND,
+                RawBytes::default(),
+                amount,
+                RawBytes::default(),
+                ExitCode::new(0),
+            );
+        }
+        let res = runtime.call::<Actor>(Method::Leave as u64, &RawBytes::default());
+        runtime.verify();
+        res
+    }
+
+    /// Submits a checkpoint committed by a single validator
+    /// expecting its signature to be verified with `key`.
+    fn submit_signed_checkpoint(
+        runtime: &mut MockRuntime,
+        validator: Address,
+        key: Address,
+        ch: &Checkpoint,
+    ) {
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, validator);
+        runtime.expect_validate_caller_any();
+        runtime.expect_verify_signature(ExpectedVerifySig {
+            sig: Signature::new_secp256k1(vec![1, 2, 3, 4]),
+            signer: key,
+            plaintext: ch.cid().to_bytes(),
+            result: Ok(()),
+        });
+        expect_gateway_send(
+            runtime,
+            ext::gateway::commit_child_checkpoint(gateway_addr(), &ch).unwrap(),
             ExitCode::new(0),
         );
+        runtime
+            .call::<Actor>(
+                Method::SubmitCheckpoint as u64,
+                &cbor::serialize(ch, "test").unwrap(),
+            )
+            .unwrap();
+        runtime.verify();
+    }
+
+    fn send_checkpoint(
+        runtime: &mut MockRuntime,
+        sender: Address,
+        checkpoint: &Checkpoint,
+        is_commit: bool,
+    ) -> Result<RawBytes, ActorError> {
+        send_checkpoint_with_penalties(runtime, sender, checkpoint, is_commit, None)
+    }
+
+    /// Submits a checkpoint expecting the stake of penalized validators
+    /// to be released from the gateway, and the burnt part of it to be burnt.
+    fn send_checkpoint_with_penalties(
+        runtime: &mut MockRuntime,
+        sender: Address,
+        checkpoint: &Checkpoint,
+        is_commit: bool,
+        penalized: Option<(TokenAmount, TokenAmount)>,
+    ) -> Result<RawBytes, ActorError> {
+        runtime.set_caller(*ACCOUNT_ACTOR_CODE_ID, sender.clone());
         runtime.expect_validate_caller_any();
         runtime.expect_verify_signature(ExpectedVerifySig {
             sig: Signature::new_secp256k1(vec![1, 2, 3, 4]),
@@ -618,7 +3555,7 @@ mod test {
 
         if is_commit {
             runtime.expect_send(
-                Address::new_id(IPC_GATEWAY_ADDR),
+                gateway_addr(),
                 ipc_gateway::Method::CommitChildCheckpoint as u64,
                 RawBytes::serialize(checkpoint)?,
                 TokenAmount::zero(),
@@ -626,6 +3563,24 @@ mod test {
                 ExitCode::new(0),
             )
         }
+        if let Some((released, burnt)) = penalized {
+            runtime.set_balance(released.clone());
+            expect_gateway_send(
+                runtime,
+                ext::gateway::release_stake(gateway_addr(), released)?,
+                ExitCode::new(0),
+            );
+            if burnt > TokenAmount::zero() {
+                runtime.expect_send(
+                    *BURNT_FUNDS_ACTOR_ADDR,
+                    METHOD_SEND,
+                    RawBytes::default(),
+                    burnt,
+                    RawBytes::default(),
+                    ExitCode::new(0),
+                );
+            }
+        }
         runtime.call::<Actor>(
             Method::SubmitCheckpoint as u64,
             &cbor::serialize(checkpoint, "test").unwrap(),